@@ -17,11 +17,20 @@ logging.getLogger("openff.toolkit").setLevel(logging.ERROR)
 arg = sys.argv[1]
 
 with open(arg, "r") as infile:
-    results = json.load(infile)
+    output = json.load(infile)
+
+results = output["results"]
+
+# geometries are in bohr unless another --length-unit was requested
+to_angstrom = {
+    "bohr": qcelemental.constants.bohr2angstroms,
+    "angstrom": 1.0,
+    "nm": 10.0,
+}[output["metadata"]["length_unit"]]
 
 
 def get_type(results):
-    typ = results[0][0]["procedure"]
+    typ = results[0]["record"]["procedure"]
     match typ:
         case "optimization":
             return OptimizationRecord
@@ -34,11 +43,11 @@ typ = get_type(results)
 for r in tqdm(
     results, desc="Converting to records and molecules", total=len(results)
 ):
-    [record, cmiles, conformers] = r
+    record, cmiles, conformers = r["record"], r["cmiles"], r["conformers"]
     molecule = Molecule.from_mapped_smiles(cmiles, allow_undefined_stereo=True)
     molecule._conformers = [
         np.array(conformers[0], float).reshape(-1, 3)
-        * qcelemental.constants.bohr2angstroms
+        * to_angstrom
         * unit.angstrom
     ]
     record = typ.parse_obj(record)
@@ -0,0 +1,104 @@
+//! Choosing how many ids to put in each request. The server's `query_limit`
+//! is only an upper bound: responses for large chunks of big molecules can be
+//! slow enough to time out, so the chunk size shrinks when responses are slow
+//! or large and grows back toward the limit when they're quick and small.
+
+use std::time::Duration;
+
+/// responses slower than this shrink the chunk size
+const SLOW: Duration = Duration::from_secs(30);
+
+/// responses faster than this, and smaller than [SMALL], grow the chunk size
+const FAST: Duration = Duration::from_secs(5);
+
+/// responses larger than this many bytes shrink the chunk size
+const LARGE: usize = 64 * 1024 * 1024;
+
+/// responses smaller than this many bytes, and faster than [FAST], grow the
+/// chunk size
+const SMALL: usize = 16 * 1024 * 1024;
+
+/// Tracks the number of ids to put in the next request based on the
+/// responses to earlier ones
+#[derive(Debug)]
+pub struct ChunkSizer {
+    size: usize,
+    max: usize,
+}
+
+impl ChunkSizer {
+    /// construct a [ChunkSizer] starting at, and never exceeding, `max`
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self { size: max, max }
+    }
+
+    /// the number of ids to put in the next request
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// update the size after a request for `len` ids took `latency` to
+    /// return `bytes`. slow or large responses halve the size of the chunk
+    /// that produced them, while fast and small responses to full-size chunks
+    /// double it
+    pub fn observe(&mut self, len: usize, latency: Duration, bytes: usize) {
+        if latency > SLOW || bytes > LARGE {
+            self.shrink(len);
+        } else if latency < FAST && bytes < SMALL && len >= self.size {
+            self.size = (self.size * 2).min(self.max);
+        }
+    }
+
+    /// update the size after a request for `len` ids failed
+    pub fn failed(&mut self, len: usize) {
+        self.shrink(len);
+    }
+
+    fn shrink(&mut self, len: usize) {
+        self.size = self.size.min(len / 2).max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapt() {
+        let mut s = ChunkSizer::new(400);
+        assert_eq!(s.size(), 400);
+
+        // a slow response halves the size
+        s.observe(400, SLOW * 2, 0);
+        assert_eq!(s.size(), 200);
+
+        // so does a large one
+        s.observe(200, FAST, LARGE + 1);
+        assert_eq!(s.size(), 100);
+
+        // a moderate response doesn't change it
+        s.observe(100, FAST, 0);
+        assert_eq!(s.size(), 100);
+
+        // fast responses to smaller chunks, like the end of the ids, don't
+        // grow it either
+        s.observe(10, Duration::ZERO, 0);
+        assert_eq!(s.size(), 100);
+
+        // but fast, small, full-size ones do, up to the maximum
+        for _ in 0..4 {
+            s.observe(s.size(), Duration::ZERO, 0);
+        }
+        assert_eq!(s.size(), 400);
+
+        // failures shrink it down to single ids but no further
+        for _ in 0..20 {
+            s.failed(s.size());
+        }
+        assert_eq!(s.size(), 1);
+
+        // a zero limit is treated as one
+        assert_eq!(ChunkSizer::new(0).size(), 1);
+    }
+}
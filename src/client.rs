@@ -1,24 +1,90 @@
-use std::{collections::HashMap, error::Error, fmt::Display};
+use std::{
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::Display,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use futures::{future::join_all, Future};
+use futures::{future::join_all, stream::FuturesUnordered, Future, StreamExt};
 use reqwest::{header::HeaderMap, Client};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, debug_span, error, info, info_span, warn, Instrument};
 
 use crate::{
-    collection::{CollectionGetBody, CollectionGetResponse},
-    make_opt_results, make_td_results,
+    chunking::ChunkSizer,
+    collection::{
+        CollectionGetBody, CollectionGetResponse, CollectionType, Records,
+    },
+    filter::SpecFilter,
+    kvstore::{KVStoreGetBody, KVStoreResponse},
+    make_go_results, make_opt_results, make_sp_results, make_td_results,
+    metrics::Metrics,
     molecule::{Molecule, MoleculeGetBody},
     procedure::{
-        OptimizationRecord, ProcedureGetBody, Response, TorsionDriveRecord,
+        FailedRecord, GridOptimizationRecord, GridPointOptimization, Meta,
+        OptimizationRecord, ProcedureGetBody, Record, Response,
+        TorsionDriveRecord,
     },
+    progress::{Callback, Event, Stage},
+    reaction::{reaction_energies, ReactionResult},
+    report::ErrorEntry,
+    result::{ResultGetBody, ResultRecord, WavefunctionGetBody},
+    ResultEntry, Status,
 };
 
+/// the maximum number of chunks [FractalClient::get_chunked] requests at once
+const MAX_IN_FLIGHT: usize = 16;
+
+/// the number of times [FractalClient::get_chunked] retries a single id that
+/// fails before giving up on it
+const MAX_RETRIES: u32 = 3;
+
+/// the number of chunks in a row that [FractalClient::get_chunked] can give
+/// up on, without retrying them, before it gives up on the rest of a stage
+const MAX_FAILURES: usize = 8;
+
+/// how long to wait for a response before treating the request as failed
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+tokio::task_local! {
+    /// the total size of the responses received while handling one chunk in
+    /// [FractalClient::get_chunked]
+    static RESPONSE_BYTES: Cell<usize>;
+}
+
+/// A range of ids requested together by [FractalClient::get_chunked]
+#[derive(Clone, Copy)]
+struct Chunk {
+    start: usize,
+    len: usize,
+    attempts: u32,
+}
+
+/// A failed request: the request couldn't be sent, the server returned an
+/// error status, or the response couldn't be decoded
 #[derive(Debug)]
-struct ClientError;
+pub struct ClientError {
+    msg: String,
+
+    /// whether the request might succeed if it's retried, possibly in smaller
+    /// pieces. this is true for timeouts and server errors but not for
+    /// failures to connect at all or responses that can't be decoded
+    transient: bool,
+}
+
+impl ClientError {
+    fn new(msg: String, transient: bool) -> Self {
+        Self { msg, transient }
+    }
+}
 
 impl Display for ClientError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ClientError")
+        write!(f, "{}", self.msg)
     }
 }
 
@@ -54,18 +120,46 @@ pub trait Body {
 
 #[derive(Clone)]
 pub struct FractalClient {
-    address: &'static str,
+    address: String,
     headers: HeaderMap,
     client: Client,
+    progress: Option<Callback>,
+    dump_dir: Option<PathBuf>,
+    metrics: Option<Arc<Metrics>>,
+
+    /// the ids given up on by [Self::get_chunked], shared between the
+    /// clients made by [Self::for_address]
+    skipped: Arc<Mutex<Vec<(Stage, String)>>>,
 }
 
 impl FractalClient {
+    /// the address of the public QCArchive server
+    pub const DEFAULT_ADDRESS: &'static str =
+        "https://api.qcarchive.molssi.org:443/";
+
+    /// construct a client connected to the public QCArchive server at
+    /// [Self::DEFAULT_ADDRESS]
     pub fn new() -> Self {
-        const ADDR: &str = "https://api.qcarchive.molssi.org:443/";
+        Self::with_address(Self::DEFAULT_ADDRESS)
+    }
+
+    /// construct a client connected to the server at `address`
+    pub fn with_address(address: impl Into<String>) -> Self {
+        let mut address = address.into();
+        if !address.ends_with('/') {
+            address.push('/');
+        }
         let mut ret = Self {
-            address: ADDR,
+            address,
             headers: HeaderMap::new(),
-            client: Client::new(),
+            client: Client::builder()
+                .timeout(TIMEOUT)
+                .build()
+                .expect("failed to build HTTP client"),
+            progress: None,
+            dump_dir: None,
+            metrics: None,
+            skipped: Arc::default(),
         };
         ret.headers
             .insert("Content-Type", "application/json".parse().unwrap());
@@ -74,6 +168,68 @@ impl FractalClient {
         ret
     }
 
+    /// report the progress of each request to `callback`, or to nothing if
+    /// it's `None`
+    pub fn with_progress(mut self, callback: Option<Callback>) -> Self {
+        self.progress = callback;
+        self
+    }
+
+    /// save the raw bodies of any responses that fail or can't be decoded to
+    /// files in `dir`, or nowhere if it's `None`
+    pub fn with_dump_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.dump_dir = dir;
+        self
+    }
+
+    /// construct a client for the server at `address`, sharing the other
+    /// settings of `self`
+    pub fn for_address(&self, address: impl Into<String>) -> Self {
+        let mut ret = Self::with_address(address)
+            .with_progress(self.progress.clone())
+            .with_dump_dir(self.dump_dir.clone())
+            .with_metrics(self.metrics.clone());
+        ret.skipped = self.skipped.clone();
+        ret
+    }
+
+    /// the ids that couldn't be retrieved by this client or any made from it
+    /// with [Self::for_address], with the stage they were skipped in
+    pub fn skipped(&self) -> Vec<(Stage, String)> {
+        self.skipped.lock().unwrap().clone()
+    }
+
+    /// record the timing and size of each request in `metrics`, or nowhere
+    /// if it's `None`
+    pub fn with_metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn report(&self, event: Event) {
+        if let Some(f) = &self.progress {
+            f(event);
+        }
+    }
+
+    /// run `f`, reporting it as a single chunk of [Stage::Assembly] and
+    /// recording its time in the metrics
+    fn assemble<T>(&self, f: impl FnOnce() -> T) -> T {
+        let stage = Stage::Assembly;
+        self.report(Event::Start { stage, items: 1 });
+        let start = Instant::now();
+        let ret = f();
+        if let Some(m) = &self.metrics {
+            m.record_assembly(start.elapsed());
+        }
+        self.report(Event::Done { stage, items: 1 });
+        ret
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
     pub async fn get_information(&self) -> Result<Information, Box<dyn Error>> {
         let url = format!("{}information", self.address);
         let response = self
@@ -83,63 +239,243 @@ impl FractalClient {
             .send()
             .await?;
         if !response.status().is_success() {
-            return Err(Box::new(ClientError));
+            return Err(Box::new(ClientError::new(
+                format!("get `information` failed with {}", response.status()),
+                false,
+            )));
         }
         let info: Information = response.json().await?;
         Ok(info)
     }
 
-    async fn get(
+    /// send `body` to `endpoint` and deserialize the response, logging its
+    /// status, size, and latency and recording them in the metrics, whether
+    /// the request succeeds or not. if the server returns an error or the
+    /// response can't be deserialized, the raw response is saved to the dump
+    /// directory if there is one
+    async fn get<T: DeserializeOwned>(
         &self,
         endpoint: &str,
         body: impl ToJson,
-    ) -> reqwest::Response {
+    ) -> Result<T, ClientError> {
         let url = format!("{}{endpoint}", self.address);
-        let ret = self
-            .client
-            .get(url)
-            .body(body.to_json().unwrap())
-            .headers(self.headers.clone())
-            .send()
-            .await
-            .unwrap();
-        if !ret.status().is_success() {
-            panic!("get `{endpoint}` failed with {ret:?}");
+        let body = body.to_json().unwrap();
+        let span = debug_span!("request", endpoint, request_bytes = body.len());
+        async {
+            let start = Instant::now();
+            // filled in as the request progresses, so that failed attempts
+            // can be recorded in the metrics too
+            let mut latency = None;
+            let mut received = 0;
+            let mut deserialize = Duration::ZERO;
+            let ret = async {
+                let response = self
+                    .client
+                    .get(url)
+                    .body(body)
+                    .headers(self.headers.clone())
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        ClientError::new(
+                            format!("get `{endpoint}`: {e}"),
+                            e.is_timeout() || !e.is_connect(),
+                        )
+                    })?;
+                let status = response.status();
+                let bytes = response.bytes().await.map_err(|e| {
+                    ClientError::new(
+                        format!("reading `{endpoint}` response: {e}"),
+                        true,
+                    )
+                })?;
+                received = bytes.len();
+                // let get_chunked see the size of the response
+                let _ = RESPONSE_BYTES.try_with(|n| n.set(n.get() + received));
+                let elapsed = start.elapsed();
+                latency = Some(elapsed);
+                debug!(
+                    status = status.as_u16(),
+                    response_bytes = received,
+                    latency_ms = elapsed.as_millis() as u64,
+                    "response"
+                );
+                if !status.is_success() {
+                    self.dump(endpoint, &bytes);
+                    let transient = status.is_server_error()
+                        || [408, 413, 429].contains(&status.as_u16());
+                    return Err(ClientError::new(
+                        format!("get `{endpoint}` failed with {status}"),
+                        transient,
+                    ));
+                }
+                let start = Instant::now();
+                let ret = serde_json::from_slice(&bytes).map_err(|e| {
+                    self.dump(endpoint, &bytes);
+                    // a complete response that doesn't match the expected
+                    // schema won't match it on another try either
+                    ClientError::new(
+                        format!("failed to decode `{endpoint}` response: {e}"),
+                        false,
+                    )
+                });
+                deserialize = start.elapsed();
+                ret
+            }
+            .await;
+            if let Some(m) = &self.metrics {
+                let latency = latency.unwrap_or_else(|| start.elapsed());
+                if ret.is_ok() {
+                    m.record_request(endpoint, latency, received, deserialize);
+                } else {
+                    m.record_failure(endpoint, latency, received);
+                }
+            }
+            ret
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// check the `meta` of a response from `endpoint` holding `len` entries.
+    /// failures and errors reported by the server are usually problems with
+    /// the query itself, so they're returned as errors that aren't retried.
+    /// responses holding fewer entries than the server found were truncated
+    /// and are returned as transient errors so that the ids are requested
+    /// again, in smaller chunks. ids the server couldn't find are only logged,
+    /// since asking again won't help
+    fn check(
+        endpoint: &str,
+        meta: &Meta,
+        len: usize,
+    ) -> Result<(), ClientError> {
+        if !meta.success {
+            return Err(ClientError::new(
+                format!("get `{endpoint}` failed: {}", meta.error_description),
+                false,
+            ));
+        }
+        if !meta.errors.is_empty() {
+            return Err(ClientError::new(
+                format!(
+                    "server reported errors for `{endpoint}`: {}",
+                    Value::from(meta.errors.clone())
+                ),
+                false,
+            ));
+        }
+        if len < meta.n_found {
+            return Err(ClientError::new(
+                format!(
+                    "partial `{endpoint}` response: got {len} of {} entries",
+                    meta.n_found
+                ),
+                true,
+            ));
+        }
+        if !meta.missing.is_empty() {
+            warn!(
+                "server couldn't find {} `{endpoint}` ids: {}",
+                meta.missing.len(),
+                meta.missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// save the raw `bytes` of a failed response from `endpoint` to a new
+    /// file in the dump directory, if there is one
+    fn dump(&self, endpoint: &str, bytes: &[u8]) {
+        let Some(dir) = &self.dump_dir else {
+            return;
+        };
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let path = dir.join(format!("{endpoint}-{stamp}.json"));
+        match std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&path, bytes))
+        {
+            Ok(()) => error!("saved failed response to {}", path.display()),
+            Err(e) => error!("failed to write {}: {e}", path.display()),
         }
-        ret
     }
 
     pub async fn get_collection(
         &self,
         body: CollectionGetBody,
     ) -> CollectionGetResponse {
-        match self.get("collection", body.clone()).await.json().await {
-            Ok(r) => r,
-            Err(_) => {
-                // this is very stupid, but I'm tired of having to comment
-                // things out and add todo!() to see the text
-                std::fs::write(
-                    "/tmp/out.json",
-                    self.get("collection", body).await.text().await.unwrap(),
-                )
-                .unwrap();
-                panic!("failed get_collection")
-            }
-        }
+        self.get("collection", body)
+            .await
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// request all of the collections in `bodies` and merge them into a
+    /// single [CollectionGetResponse]
+    pub async fn get_collections(
+        &self,
+        bodies: Vec<CollectionGetBody>,
+    ) -> CollectionGetResponse {
+        self.report(Event::Start {
+            stage: Stage::Collection,
+            items: bodies.len(),
+        });
+        let responses = join_all(bodies.into_iter().map(|b| async {
+            let ret = self.get_collection(b).await;
+            self.report(Event::Done {
+                stage: Stage::Collection,
+                items: 1,
+            });
+            ret
+        }))
+        .await;
+        CollectionGetResponse::merge(responses)
     }
 
     pub async fn get_procedure<T: for<'a> Deserialize<'a>>(
         &self,
         body: ProcedureGetBody,
-    ) -> Response<T> {
-        self.get("procedure", body).await.json().await.unwrap()
+    ) -> Result<Response<T>, ClientError> {
+        let ret: Response<T> = self.get("procedure", body).await?;
+        Self::check("procedure", &ret.meta, ret.data.len())?;
+        Ok(ret)
+    }
+
+    pub async fn get_result<T: DeserializeOwned>(
+        &self,
+        body: ResultGetBody,
+    ) -> Result<Response<T>, ClientError> {
+        let ret: Response<T> = self.get("result", body).await?;
+        Self::check("result", &ret.meta, ret.data.len())?;
+        Ok(ret)
+    }
+
+    pub async fn get_wavefunction(
+        &self,
+        body: WavefunctionGetBody,
+    ) -> Result<Response<Value>, ClientError> {
+        let ret: Response<Value> = self.get("wavefunctionstore", body).await?;
+        Self::check("wavefunctionstore", &ret.meta, ret.data.len())?;
+        Ok(ret)
     }
 
     pub async fn get_molecule(
         &self,
         body: MoleculeGetBody,
-    ) -> Response<Molecule> {
-        self.get("molecule", body).await.json().await.unwrap()
+    ) -> Result<Response<Molecule>, ClientError> {
+        let ret: Response<Molecule> = self.get("molecule", body).await?;
+        Self::check("molecule", &ret.meta, ret.data.len())?;
+        Ok(ret)
+    }
+
+    pub async fn get_kvstore(
+        &self,
+        body: KVStoreGetBody,
+    ) -> Result<KVStoreResponse, ClientError> {
+        let ret: KVStoreResponse = self.get("kvstore", body).await?;
+        Self::check("kvstore", &ret.meta, ret.data.len())?;
+        Ok(ret)
     }
 
     /// Make an information request to the server to obtain the query limit
@@ -147,41 +483,202 @@ impl FractalClient {
         self.get_information().await.unwrap().query_limit
     }
 
+    /// record that `ids` were given up on in `stage`, reporting them as done
+    /// so the progress bars still finish
+    fn skip<'a>(
+        &self,
+        stage: Stage,
+        ids: impl IntoIterator<Item = &'a String>,
+    ) {
+        let mut skipped = self.skipped.lock().unwrap();
+        let before = skipped.len();
+        skipped.extend(ids.into_iter().map(|id| (stage, id.clone())));
+        let items = skipped.len() - before;
+        drop(skipped);
+        self.report(Event::Done { stage, items });
+    }
+
+    /// request `ids` with `method` in chunks, keeping up to
+    /// [MAX_IN_FLIGHT] requests running at once. chunks start at
+    /// `query_limit` ids and are resized by a [ChunkSizer] as responses
+    /// arrive. a chunk that fails is split in half and retried, down to single
+    /// ids, which are retried up to [MAX_RETRIES] times before being reported
+    /// and skipped. chunks failing for reasons a retry won't fix, like being
+    /// unable to connect or a response that can't be decoded, are skipped
+    /// right away, and after [MAX_FAILURES] chunks in a row are skipped the
+    /// rest of the stage is skipped too. skipped ids are collected in
+    /// [Self::skipped]. the responses are returned in the order of `ids`
     async fn get_chunked<'a, B, R, F, Q>(
         &'a self,
+        stage: Stage,
         method: Q,
         ids: &[String],
-        chunk_size: usize,
+        query_limit: usize,
     ) -> Vec<R>
     where
         B: Body,
-        F: Future<Output = R>,
+        F: Future<Output = Result<R, ClientError>>,
         Q: Fn(&'a FractalClient, B) -> F,
     {
-        let mut futures = Vec::new();
-        for chunk in ids.chunks(chunk_size) {
-            let proc = B::new(chunk.to_vec());
-            futures.push(method(self, proc));
+        let span = info_span!("stage", %stage, ids = ids.len(), query_limit);
+        async {
+            let start = Instant::now();
+            self.report(Event::Start {
+                stage,
+                items: ids.len(),
+            });
+            let mut sizer = ChunkSizer::new(query_limit);
+            // chunks that failed and need to be retried
+            let mut retry = VecDeque::new();
+            // the start of the ids not yet put in a chunk
+            let mut next = 0;
+            let mut in_flight = FuturesUnordered::new();
+            let mut ret = Vec::new();
+            // whether each id has been retrieved or given up on
+            let mut settled = vec![false; ids.len()];
+            // chunks given up on since the last success
+            let mut failures = 0;
+            loop {
+                while in_flight.len() < MAX_IN_FLIGHT {
+                    let chunk = match retry.pop_front() {
+                        Some(chunk) => chunk,
+                        None if next < ids.len() => {
+                            let len = sizer.size().min(ids.len() - next);
+                            next += len;
+                            Chunk {
+                                start: next - len,
+                                len,
+                                attempts: 0,
+                            }
+                        }
+                        None => break,
+                    };
+                    let body = B::new(
+                        ids[chunk.start..chunk.start + chunk.len].to_vec(),
+                    );
+                    let fut = method(self, body);
+                    in_flight.push(async move {
+                        if chunk.attempts > 0 {
+                            let wait = Duration::from_secs(1 << chunk.attempts);
+                            tokio::time::sleep(wait).await;
+                        }
+                        let start = Instant::now();
+                        let (res, bytes) = RESPONSE_BYTES
+                            .scope(Cell::new(0), async {
+                                let res = fut.await;
+                                (res, RESPONSE_BYTES.with(Cell::get))
+                            })
+                            .await;
+                        (chunk, res, start.elapsed(), bytes)
+                    });
+                }
+                let Some((chunk, res, latency, bytes)) = in_flight.next().await
+                else {
+                    break;
+                };
+                let range = chunk.start..chunk.start + chunk.len;
+                let e = match res {
+                    Ok(r) => {
+                        failures = 0;
+                        sizer.observe(chunk.len, latency, bytes);
+                        self.report(Event::Done {
+                            stage,
+                            items: chunk.len,
+                        });
+                        settled[range].fill(true);
+                        ret.push((chunk.start, r));
+                        continue;
+                    }
+                    Err(e) => e,
+                };
+                if !e.transient {
+                    error!("skipping {} ids after: {e}", chunk.len);
+                } else if chunk.len > 1 {
+                    sizer.failed(chunk.len);
+                    warn!("splitting chunk of {} ids after: {e}", chunk.len);
+                    let half = chunk.len / 2;
+                    retry.push_front(Chunk {
+                        start: chunk.start + half,
+                        len: chunk.len - half,
+                        attempts: 0,
+                    });
+                    retry.push_front(Chunk {
+                        start: chunk.start,
+                        len: half,
+                        attempts: 0,
+                    });
+                    continue;
+                } else if chunk.attempts < MAX_RETRIES {
+                    warn!("retrying {} after: {e}", ids[chunk.start]);
+                    retry.push_back(Chunk {
+                        attempts: chunk.attempts + 1,
+                        ..chunk
+                    });
+                    continue;
+                } else {
+                    error!(
+                        "giving up on {} after {} attempts: {e}",
+                        ids[chunk.start],
+                        chunk.attempts + 1
+                    );
+                }
+                settled[range.clone()].fill(true);
+                self.skip(stage, &ids[range]);
+                // chunks that are split or retried don't count, so a wave of
+                // timeouts on oversized chunks can't end the stage before the
+                // smaller pieces have had a chance
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    let rest: Vec<_> = (0..ids.len())
+                        .filter(|&i| !settled[i])
+                        .map(|i| &ids[i])
+                        .collect();
+                    error!(
+                        "giving up on the remaining {} ids after skipping \
+                         {failures} chunks in a row, the last after: {e}",
+                        rest.len()
+                    );
+                    self.skip(stage, rest);
+                    break;
+                }
+            }
+            debug!(latency_ms = start.elapsed().as_millis() as u64, "done");
+            ret.sort_by_key(|(start, _)| *start);
+            ret.into_iter().map(|(_, r)| r).collect()
         }
-        join_all(futures).await
+        .instrument(span)
+        .await
     }
 
+    /// request the OptimizationRecords in `collection` and their final
+    /// molecules. records not matching `spec` are dropped before requesting
+    /// anything else for them
     pub async fn optimization_records(
         &self,
         collection: CollectionGetResponse,
         query_limit: usize,
-    ) -> Vec<(OptimizationRecord, String, Vec<Vec<f64>>)> {
+        spec: &SpecFilter,
+    ) -> Vec<ResultEntry<OptimizationRecord>> {
         // request the OptimizationRecords corresponding to the ids in the
         // collection
-        let records: Vec<OptimizationRecord> = self
-            .get_chunked(Self::get_procedure, &collection.ids(), query_limit)
+        let mut records: Vec<OptimizationRecord> = self
+            .get_chunked(
+                Stage::Procedures,
+                Self::get_procedure,
+                &collection.ids(),
+                query_limit,
+            )
             .await
             .into_iter()
             .flatten()
             .filter(|r: &OptimizationRecord| r.status.is_complete())
             .collect();
 
-        eprintln!("{} optimization records", records.len());
+        if !spec.is_empty() {
+            spec.apply(&mut records);
+        }
+
+        info!("{} optimization records", records.len());
 
         // get the molecule record ids corresponding to the final geometries.
         // molecule_ids is a map of final_molecule_ids -> original opt record
@@ -195,43 +692,59 @@ impl FractalClient {
         }
         let ids: Vec<_> = molecule_ids.values().cloned().collect();
 
-        eprintln!("asking for {} molecules", molecule_ids.len());
+        info!("asking for {} molecules", molecule_ids.len());
 
         // get the final molecules from each optimization trajectory and store
         // as a map of id -> mol
         let molecules: HashMap<_, _> = self
-            .get_chunked(Self::get_molecule, &ids, query_limit)
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
             .await
             .into_iter()
             .flatten()
             .map(|m| (m.id.clone(), m))
             .collect();
 
-        let results: Vec<_> = collection
-            .data
-            .into_iter()
-            .flat_map(|ds| ds.records.into_values())
-            .collect();
+        let results = collection.into_results();
 
-        make_opt_results(results, records, molecule_ids, molecules)
+        self.assemble(|| {
+            make_opt_results(results, records, molecule_ids, molecules)
+        })
     }
 
+    /// request the TorsionDriveRecords in `collection` and their final
+    /// molecules. records not matching `spec` are dropped before requesting
+    /// anything else for them
     pub async fn torsion_drive_records(
         &self,
         collection: CollectionGetResponse,
         query_limit: usize,
-    ) -> Vec<(TorsionDriveRecord, String, Vec<Vec<f64>>)> {
+        spec: &SpecFilter,
+    ) -> Vec<ResultEntry<TorsionDriveRecord>> {
         // request the TorsionDriveRecords corresponding to the ids in the
         // collection
-        let records: Vec<TorsionDriveRecord> = self
-            .get_chunked(Self::get_procedure, &collection.ids(), query_limit)
+        let mut records: Vec<TorsionDriveRecord> = self
+            .get_chunked(
+                Stage::Procedures,
+                Self::get_procedure,
+                &collection.ids(),
+                query_limit,
+            )
             .await
             .into_iter()
             .flatten()
             .filter(|r: &TorsionDriveRecord| r.status.is_complete())
             .collect();
 
-        eprintln!("{} torsion drive records", records.len());
+        if !spec.is_empty() {
+            spec.apply(&mut records);
+        }
+
+        info!("{} torsion drive records", records.len());
 
         // this is a map of optimization_id -> (record_id, grid_id)
         let mut intermediate_ids: HashMap<_, _> = records
@@ -244,7 +757,12 @@ impl FractalClient {
         // get the optimization records corresponding to each position in the
         // TorsionDrive
         let responses: Vec<OptimizationRecord> = self
-            .get_chunked(Self::get_procedure, &optimization_ids, query_limit)
+            .get_chunked(
+                Stage::Optimizations,
+                Self::get_procedure,
+                &optimization_ids,
+                query_limit,
+            )
             .await
             .into_iter()
             .flatten()
@@ -253,34 +771,571 @@ impl FractalClient {
         // this is a map of (record_id, grid_id) -> opt_record_id
         let mut molecule_ids = HashMap::with_capacity(optimization_ids.len());
         for opt_record in responses {
-            molecule_ids.insert(
-                intermediate_ids
-                    .remove(&opt_record.id)
-                    .expect("duplicate opt id?"),
-                opt_record.final_molecule,
-            );
+            let Some(key) = intermediate_ids.remove(&opt_record.id) else {
+                warn!(
+                    "ignoring unrequested or duplicate optimization {}",
+                    opt_record.id
+                );
+                continue;
+            };
+            molecule_ids.insert(key, opt_record.final_molecule);
         }
         let ids: Vec<_> = molecule_ids.values().cloned().collect();
 
-        eprintln!("asking for {} molecules", ids.len());
+        info!("asking for {} molecules", ids.len());
 
         // get the final molecules from each optimization trajectory
         let molecules: HashMap<_, _> = self
-            .get_chunked(Self::get_molecule, &ids, query_limit)
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .map(|mol| (mol.id.clone(), mol))
+            .collect();
+
+        info!("received {} molecules", molecules.len());
+
+        let results = collection.into_results();
+
+        self.assemble(|| {
+            make_td_results(results, records, molecule_ids, molecules)
+        })
+    }
+
+    /// request the [GridOptimizationRecord]s in `collection`, the
+    /// optimization at each of their grid points, and the final molecules of
+    /// those optimizations, like [Self::torsion_drive_records]
+    pub async fn grid_optimization_records(
+        &self,
+        collection: CollectionGetResponse,
+        query_limit: usize,
+        spec: &SpecFilter,
+    ) -> Vec<ResultEntry<GridOptimizationRecord>> {
+        let mut records: Vec<GridOptimizationRecord> = self
+            .get_chunked(
+                Stage::Procedures,
+                Self::get_procedure,
+                &collection.ids(),
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|r: &GridOptimizationRecord| r.status.is_complete())
+            .collect();
+
+        if !spec.is_empty() {
+            spec.apply(&mut records);
+        }
+
+        info!("{} grid optimization records", records.len());
+
+        // this is a map of optimization_id -> (record_id, grid_id)
+        let mut intermediate_ids: HashMap<_, _> = records
+            .iter()
+            .flat_map(GridOptimizationRecord::optimizations)
+            .collect();
+        let optimization_ids: Vec<String> =
+            intermediate_ids.keys().cloned().collect();
+
+        let responses: Vec<OptimizationRecord> = self
+            .get_chunked(
+                Stage::Optimizations,
+                Self::get_procedure,
+                &optimization_ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+
+        // this is a map of (record_id, grid_id) -> final_molecule_id
+        let mut molecule_ids = HashMap::with_capacity(optimization_ids.len());
+        for opt_record in responses {
+            let Some(key) = intermediate_ids.remove(&opt_record.id) else {
+                warn!(
+                    "ignoring unrequested or duplicate optimization {}",
+                    opt_record.id
+                );
+                continue;
+            };
+            molecule_ids.insert(key, opt_record.final_molecule);
+        }
+        let ids: Vec<_> = molecule_ids.values().cloned().collect();
+
+        info!("asking for {} molecules", ids.len());
+
+        let molecules: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
             .await
             .into_iter()
             .flatten()
             .map(|mol| (mol.id.clone(), mol))
             .collect();
 
-        eprintln!("received {} molecules", molecules.len());
+        info!("received {} molecules", molecules.len());
+
+        let results = collection.into_results();
 
-        let results: Vec<_> = collection
+        self.assemble(|| {
+            make_go_results(results, records, molecule_ids, molecules)
+        })
+    }
+
+    /// request the single-point [ResultRecord]s in `collection` and their
+    /// molecules. collections built from a ResultCollection refer to the
+    /// records by id, while datasets retrieved from the server only list
+    /// their molecules, so in that case every result on those molecules
+    /// matching `spec` is requested
+    pub async fn single_point_records(
+        &self,
+        collection: CollectionGetResponse,
+        query_limit: usize,
+        spec: &SpecFilter,
+    ) -> Vec<ResultEntry<ResultRecord>> {
+        let by_molecule = collection
             .data
+            .iter()
+            .any(|ds| matches!(ds.records, Records::Vec(_)));
+        let mut records: Vec<ResultRecord> = self
+            .get_chunked(
+                Stage::Procedures,
+                |client, body: ResultGetBody| {
+                    let body = if by_molecule {
+                        body.by_molecule().with_spec(spec)
+                    } else {
+                        body
+                    };
+                    client.get_result(body)
+                },
+                &collection.ids(),
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|r: &ResultRecord| r.status.is_complete())
+            .collect();
+
+        if !spec.is_empty() {
+            spec.apply(&mut records);
+        }
+
+        info!("{} single point records", records.len());
+
+        let mut ids: Vec<_> =
+            records.iter().map(|r| r.molecule.clone()).collect();
+        ids.sort();
+        ids.dedup();
+
+        info!("asking for {} molecules", ids.len());
+
+        let molecules: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .map(|m| (m.id.clone(), m))
+            .collect();
+
+        // datasets have no results to take cmiles from
+        let results = if by_molecule {
+            Vec::new()
+        } else {
+            collection.into_results()
+        };
+
+        self.assemble(|| make_sp_results(results, records, molecules))
+    }
+
+    /// request the single-point [ResultRecord]s on the molecules in the
+    /// reaction datasets in `collection` and combine their energies into
+    /// reaction energies for each QC specification matching `spec`
+    pub async fn reaction_records(
+        &self,
+        collection: CollectionGetResponse,
+        query_limit: usize,
+        spec: &SpecFilter,
+    ) -> Vec<ReactionResult> {
+        let ids = collection.ids();
+        let mut records: Vec<ResultRecord> = self
+            .get_chunked(
+                Stage::Procedures,
+                |client, body: ResultGetBody| {
+                    client.get_result(body.by_molecule().with_spec(spec))
+                },
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|r: &ResultRecord| r.status.is_complete())
+            .collect();
+
+        if !spec.is_empty() {
+            spec.apply(&mut records);
+        }
+
+        info!("{} single point records", records.len());
+        info!("asking for {} molecules", ids.len());
+
+        let molecules: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .map(|m| (m.id.clone(), m))
+            .collect();
+
+        let reactions = collection.into_reactions();
+        self.assemble(|| reaction_energies(&reactions, &records, &molecules))
+    }
+
+    /// request the records in `ids` that ended in [Status::Error], along with
+    /// their errors and stdout from the key-value store, and classify them
+    pub async fn error_report(
+        &self,
+        ids: &[String],
+        query_limit: usize,
+    ) -> Vec<ErrorEntry> {
+        let records: Vec<FailedRecord> = self
+            .get_chunked(
+                Stage::Errors,
+                |client, body: ProcedureGetBody| {
+                    client.get_procedure(body.with_status(Status::Error))
+                },
+                ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|r: &FailedRecord| !r.status.is_complete())
+            .collect();
+
+        self.classify_errors(records, query_limit).await
+    }
+
+    /// like [Self::error_report] for single-point [ResultRecord]s, which are
+    /// requested from `result` rather than `procedure`. if `by_molecule` is
+    /// true, `ids` are molecule ids, as in datasets listing their molecules,
+    /// and the failed results on those molecules matching `spec` are
+    /// requested instead
+    pub async fn result_error_report(
+        &self,
+        ids: &[String],
+        by_molecule: bool,
+        spec: &SpecFilter,
+        query_limit: usize,
+    ) -> Vec<ErrorEntry> {
+        let records: Vec<FailedRecord> = self
+            .get_chunked(
+                Stage::Errors,
+                |client, body: ResultGetBody| {
+                    let body = body.with_status(Status::Error);
+                    let body = if by_molecule {
+                        body.by_molecule().with_spec(spec)
+                    } else {
+                        body
+                    };
+                    client.get_result(body)
+                },
+                ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|r: &FailedRecord| !r.status.is_complete())
+            .collect();
+
+        self.classify_errors(records, query_limit).await
+    }
+
+    /// request the errors of the failed records in `collection`, a dataset
+    /// of type `typ`, from the endpoint holding that type of record with
+    /// [Self::error_report] or [Self::result_error_report]
+    pub async fn collection_error_report(
+        &self,
+        collection: &CollectionGetResponse,
+        typ: CollectionType,
+        spec: &SpecFilter,
+        query_limit: usize,
+    ) -> Vec<ErrorEntry> {
+        let ids = collection.ids();
+        match typ {
+            CollectionType::SinglePoint | CollectionType::Reaction => {
+                let by_molecule = collection
+                    .data
+                    .iter()
+                    .any(|ds| !matches!(ds.records, Records::Map(_)));
+                self.result_error_report(&ids, by_molecule, spec, query_limit)
+                    .await
+            }
+            CollectionType::TorsionDrive
+            | CollectionType::Optimization
+            | CollectionType::GridOptimization => {
+                self.error_report(&ids, query_limit).await
+            }
+        }
+    }
+
+    /// request the error and stdout blobs of `records` from the key-value
+    /// store and classify them
+    async fn classify_errors(
+        &self,
+        records: Vec<FailedRecord>,
+        query_limit: usize,
+    ) -> Vec<ErrorEntry> {
+        info!("{} failed records", records.len());
+
+        let blob_ids: Vec<_> = records
+            .iter()
+            .flat_map(|r| [r.error.clone(), r.stdout.clone()])
+            .flatten()
+            .collect();
+
+        let blobs: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Errors,
+                Self::get_kvstore,
+                &blob_ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+
+        records
+            .into_iter()
+            .map(|r| ErrorEntry::new(r, &blobs))
+            .collect()
+    }
+
+    /// request the initial molecules of the records in `entries` and store
+    /// them in [ResultEntry::initial_molecules]. if any of an entry's initial
+    /// molecules are missing, none of them are stored for that entry
+    pub async fn initial_molecules<R: Record>(
+        &self,
+        entries: &mut [ResultEntry<R>],
+        query_limit: usize,
+    ) {
+        let mut ids: Vec<_> = entries
+            .iter()
+            .flat_map(|e| e.record.initial_molecules())
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        info!("asking for {} initial molecules", ids.len());
+
+        let molecules: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .map(|mol| (mol.id.clone(), mol))
+            .collect();
+
+        for entry in entries {
+            let initial: Option<Vec<_>> = entry
+                .record
+                .initial_molecules()
+                .iter()
+                .map(|id| molecules.get(id).cloned())
+                .collect();
+            entry.initial_molecules = initial.unwrap_or_else(|| {
+                warn!("missing initial molecules for {}", entry.record.id());
+                Vec::new()
+            });
+        }
+    }
+
+    /// request the full wavefunctions of the records in `entries` that kept
+    /// one and store them in [ResultRecord::wavefunction_data]
+    pub async fn wavefunctions(
+        &self,
+        entries: &mut [ResultEntry<ResultRecord>],
+        query_limit: usize,
+    ) {
+        let mut ids: Vec<_> = entries
+            .iter()
+            .filter(|e| e.record.has_wavefunction())
+            .filter_map(|e| e.record.wavefunction_data_id.clone())
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        info!("asking for {} wavefunctions", ids.len());
+
+        let wavefunctions: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Wavefunctions,
+                Self::get_wavefunction,
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .filter_map(|w| Some((w.get("id")?.as_str()?.to_owned(), w)))
+            .collect();
+
+        for entry in entries {
+            if let Some(id) = &entry.record.wavefunction_data_id {
+                entry.record.wavefunction_data = wavefunctions.get(id).cloned();
+            }
+        }
+    }
+
+    /// request every optimization in the `optimization_history` of the
+    /// torsion drives in `entries`, not just the minimum at each grid point,
+    /// along with their final molecules, and store them in
+    /// [TorsionDriveRecord::all_optimizations]
+    pub async fn all_optimizations(
+        &self,
+        entries: &mut [ResultEntry<TorsionDriveRecord>],
+        query_limit: usize,
+    ) {
+        let mut ids: Vec<String> = entries
+            .iter()
+            // the grid ids were already checked by make_td_results
+            .flat_map(|e| e.record.optimization_history().unwrap_or_default())
+            .map(|(_, opt_id, _)| opt_id.clone())
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        info!("asking for {} optimizations", ids.len());
+
+        let optimizations: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Optimizations,
+                Self::get_procedure,
+                &ids,
+                query_limit,
+            )
+            .await
             .into_iter()
-            .flat_map(|ds| ds.records.into_values())
+            .flatten()
+            .map(|r: OptimizationRecord| (r.id.clone(), r))
+            .collect();
+
+        let mut ids: Vec<_> = optimizations
+            .values()
+            .map(|r| r.final_molecule.clone())
             .collect();
+        ids.sort();
+        ids.dedup();
+
+        info!("asking for {} molecules", ids.len());
+
+        let molecules: HashMap<_, _> = self
+            .get_chunked(
+                Stage::Molecules,
+                Self::get_molecule,
+                &ids,
+                query_limit,
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .map(|m| (m.id.clone(), m))
+            .collect();
+
+        for entry in entries {
+            let record = &entry.record;
+            let mut all = Vec::new();
+            let history = record.optimization_history().unwrap_or_default();
+            for (grid_id, opt_id, minimum) in history {
+                let Some(opt) = optimizations.get(opt_id) else {
+                    warn!("missing optimization {opt_id} for {}", record.id);
+                    continue;
+                };
+                let Some(mol) = molecules.get(&opt.final_molecule) else {
+                    warn!(
+                        "missing final molecule {} of optimization {opt_id} \
+                         for {}",
+                        opt.final_molecule, record.id
+                    );
+                    continue;
+                };
+                all.push(GridPointOptimization {
+                    grid_id: grid_id.clone(),
+                    optimization_id: opt_id.clone(),
+                    minimum,
+                    energy: opt.energy(),
+                    geometry: mol.geometry.clone(),
+                });
+            }
+            entry.record.all_optimizations = all;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a [Body] holding the ids directly, for faking requests
+    struct Ids(Vec<String>);
+
+    impl Body for Ids {
+        fn new(ids: Vec<String>) -> Self {
+            Self(ids)
+        }
+    }
 
-        make_td_results(results, records, molecule_ids, molecules)
+    #[tokio::test]
+    async fn split_timeouts() {
+        // enough full-size chunks to fill every request slot, all of which
+        // time out until they're split down to two ids
+        let ids: Vec<_> =
+            (0..MAX_IN_FLIGHT * 10).map(|i| i.to_string()).collect();
+        let client = FractalClient::new();
+        let got: Vec<Vec<String>> = client
+            .get_chunked(
+                Stage::Molecules,
+                |_, body: Ids| async move {
+                    if body.0.len() > 2 {
+                        Err(ClientError::new("timed out".to_owned(), true))
+                    } else {
+                        Ok(body.0)
+                    }
+                },
+                &ids,
+                10,
+            )
+            .await;
+        assert_eq!(got.concat(), ids);
+        assert!(client.skipped().is_empty());
     }
 }
@@ -1,9 +1,16 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    path::Path,
+    str::FromStr,
+};
 
 use openff_toolkit::qcsubmit::results::TorsionDriveResultCollection;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::reaction::ReactionEntry;
+
 #[derive(Clone, Serialize)]
 struct QueryFilter {
     include: Option<bool>,
@@ -22,11 +29,13 @@ pub struct CollectionGetBody {
     data: Data,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CollectionType {
     TorsionDrive,
     Optimization,
     SinglePoint,
+    GridOptimization,
+    Reaction,
 }
 
 impl From<CollectionType> for String {
@@ -35,6 +44,10 @@ impl From<CollectionType> for String {
             CollectionType::TorsionDrive => String::from("torsiondrivedataset"),
             CollectionType::Optimization => String::from("optimization"),
             CollectionType::SinglePoint => String::from("dataset"),
+            CollectionType::GridOptimization => {
+                String::from("gridoptimizationdataset")
+            }
+            CollectionType::Reaction => String::from("reactiondataset"),
         }
     }
 }
@@ -47,11 +60,56 @@ impl FromStr for CollectionType {
             "TorsionDrive" => Ok(Self::TorsionDrive),
             "Optimization" => Ok(Self::Optimization),
             "SinglePoint" => Ok(Self::SinglePoint),
+            "GridOptimization" => Ok(Self::GridOptimization),
+            "Reaction" => Ok(Self::Reaction),
             e => Err(format!("unmatched CollectionType: `{e}`")),
         }
     }
 }
 
+impl CollectionType {
+    /// the `type` of the individual entries in the corresponding qcsubmit
+    /// ResultCollection
+    pub const fn entry_type(&self) -> &'static str {
+        match self {
+            CollectionType::TorsionDrive => "torsion",
+            CollectionType::Optimization => "optimization",
+            CollectionType::SinglePoint => "basic",
+            CollectionType::GridOptimization => "gridoptimization",
+            CollectionType::Reaction => "reaction",
+        }
+    }
+
+    /// the `type` of the corresponding qcsubmit ResultCollection. qcsubmit
+    /// has no collection for grid optimizations or reactions, so those names
+    /// are only understood by garage-door
+    pub const fn result_collection_type(&self) -> &'static str {
+        match self {
+            CollectionType::TorsionDrive => "TorsionDriveResultCollection",
+            CollectionType::Optimization => "OptimizationResultCollection",
+            CollectionType::SinglePoint => "BasicResultCollection",
+            CollectionType::GridOptimization => {
+                "GridOptimizationResultCollection"
+            }
+            CollectionType::Reaction => "ReactionResultCollection",
+        }
+    }
+
+    /// the inverse of [Self::result_collection_type]. reaction collections
+    /// can't be saved since their entries aren't records, so they aren't
+    /// recognized
+    pub fn from_result_collection_type(s: &str) -> Option<Self> {
+        [
+            Self::TorsionDrive,
+            Self::Optimization,
+            Self::SinglePoint,
+            Self::GridOptimization,
+        ]
+        .into_iter()
+        .find(|t| t.result_collection_type() == s)
+    }
+}
+
 impl CollectionGetBody {
     /// Construct a new [CollectionGetBody] with `collection_type` and `name`.
     pub fn new(
@@ -75,6 +133,8 @@ impl CollectionGetBody {
 struct Attributes {
     canonical_isomeric_explicit_hydrogen_mapped_smiles: String,
     inchi_key: String,
+    #[serde(default)]
+    fixed_hydrogen_inchi_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -84,6 +144,11 @@ pub struct TorsionDriveResult {
     attributes: Attributes,
 
     object_map: HashMap<String, String>,
+
+    /// the name of the [DataSet] this result came from, if known. This isn't
+    /// part of the response but is filled in by [DataSet::into_results]
+    #[serde(skip)]
+    pub dataset: Option<String>,
 }
 
 impl TorsionDriveResult {
@@ -104,6 +169,15 @@ impl TorsionDriveResult {
     pub const fn inchi_key(&self) -> &String {
         &self.attributes.inchi_key
     }
+
+    /// return the fixed-hydrogen InChI key if there is one, otherwise the
+    /// standard InChI key. this is the one qcsubmit uses in its entries
+    pub fn fixed_hydrogen_inchi_key(&self) -> &String {
+        self.attributes
+            .fixed_hydrogen_inchi_key
+            .as_ref()
+            .unwrap_or(&self.attributes.inchi_key)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -123,6 +197,7 @@ pub struct BasicResult {
 pub enum Records {
     Map(HashMap<String, TorsionDriveResult>),
     Vec(Vec<BasicResult>),
+    Reactions(Vec<ReactionEntry>),
 }
 
 impl Records {
@@ -135,8 +210,10 @@ impl Records {
                     name: r.name,
                     attributes: Attributes::default(),
                     object_map: HashMap::new(),
+                    dataset: None,
                 })
                 .collect(),
+            Records::Reactions(_) => Vec::new(),
         }
     }
 }
@@ -153,6 +230,32 @@ pub struct DataSet {
     pub records: Records,
 }
 
+impl DataSet {
+    /// consume `self` and return its records, tagged with the name of the
+    /// dataset
+    pub fn into_results(self) -> Vec<TorsionDriveResult> {
+        let dataset = (!self.name.is_empty()).then_some(self.name);
+        let mut ret = self.records.into_values();
+        for r in &mut ret {
+            r.dataset.clone_from(&dataset);
+        }
+        ret
+    }
+
+    /// consume `self` and return its reaction entries, tagged with the name
+    /// of the dataset. returns nothing for datasets of other types
+    pub fn into_reactions(self) -> Vec<ReactionEntry> {
+        let Records::Reactions(mut ret) = self.records else {
+            return Vec::new();
+        };
+        let dataset = (!self.name.is_empty()).then_some(self.name);
+        for r in &mut ret {
+            r.dataset.clone_from(&dataset);
+        }
+        ret
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CollectionGetResponse {
     pub meta: HashMap<String, Value>,
@@ -160,6 +263,9 @@ pub struct CollectionGetResponse {
 }
 
 impl CollectionGetResponse {
+    /// return the record ids in the collection, or the molecule ids for
+    /// datasets listing molecules instead of records. ids shared between
+    /// multiple datasets are only included once
     pub fn ids(&self) -> Vec<String> {
         let mut ret = Vec::new();
         for ds in &self.data {
@@ -170,28 +276,76 @@ impl CollectionGetResponse {
                 Records::Vec(v) => {
                     ret.extend(v.iter().map(|rec| rec.molecule_id.clone()))
                 }
+                Records::Reactions(v) => ret.extend(
+                    v.iter().flat_map(ReactionEntry::molecule_ids).cloned(),
+                ),
             };
         }
+        let mut seen = HashSet::new();
+        ret.retain(|id| seen.insert(id.clone()));
+        ret
+    }
+
+    /// consume `self` and return the results from all of its datasets, in the
+    /// order the datasets appear
+    pub fn into_results(self) -> Vec<TorsionDriveResult> {
+        self.data
+            .into_iter()
+            .flat_map(DataSet::into_results)
+            .collect()
+    }
+
+    /// consume `self` and return the reaction entries from all of its
+    /// datasets
+    pub fn into_reactions(self) -> Vec<ReactionEntry> {
+        self.data
+            .into_iter()
+            .flat_map(DataSet::into_reactions)
+            .collect()
+    }
+
+    /// combine several responses into one, keeping the order of their
+    /// datasets
+    pub fn merge(responses: impl IntoIterator<Item = Self>) -> Self {
+        let mut ret = Self {
+            meta: HashMap::new(),
+            data: Vec::new(),
+        };
+        for r in responses {
+            for (k, v) in r.meta {
+                ret.meta.entry(k).or_insert(v);
+            }
+            ret.data.extend(r.data);
+        }
         ret
     }
 }
 
-impl From<TorsionDriveResultCollection> for CollectionGetResponse {
-    fn from(value: TorsionDriveResultCollection) -> Self {
-        let mut records = HashMap::with_capacity(value.entries.len());
-        for entries in value.entries.into_values() {
-            for v in entries {
-                records.insert(
-                    v.record_id.clone(),
-                    TorsionDriveResult {
-                        name: v.cmiles.clone(),
-                        attributes: Attributes {
-                            canonical_isomeric_explicit_hydrogen_mapped_smiles: v.cmiles,
-                            inchi_key: v.inchi_key },
-                        object_map: HashMap::from([("default".to_string(), v.record_id)]),
+impl CollectionGetResponse {
+    /// build a response holding a single unnamed dataset from a sequence of
+    /// (record_id, cmiles, inchi_key) entries
+    fn from_entries(
+        entries: impl IntoIterator<Item = (String, String, String)>,
+    ) -> Self {
+        let mut records = HashMap::new();
+        for (record_id, cmiles, inchi_key) in entries {
+            records.insert(
+                record_id.clone(),
+                TorsionDriveResult {
+                    name: cmiles.clone(),
+                    attributes: Attributes {
+                        canonical_isomeric_explicit_hydrogen_mapped_smiles:
+                            cmiles,
+                        inchi_key,
+                        fixed_hydrogen_inchi_key: None,
                     },
-                );
-            }
+                    object_map: HashMap::from([(
+                        "default".to_string(),
+                        record_id,
+                    )]),
+                    dataset: None,
+                },
+            );
         }
         Self {
             meta: HashMap::new(),
@@ -204,3 +358,159 @@ impl From<TorsionDriveResultCollection> for CollectionGetResponse {
         }
     }
 }
+
+/// A single entry in a [ResultCollection]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CollectionEntry {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub record_id: String,
+    pub cmiles: String,
+    pub inchi_key: String,
+}
+
+/// The JSON representation shared by qcsubmit's
+/// `TorsionDriveResultCollection`, `OptimizationResultCollection`, and
+/// `BasicResultCollection`. The entries are keyed by the address of the server
+/// they came from
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResultCollection {
+    pub entries: BTreeMap<String, Vec<CollectionEntry>>,
+
+    #[serde(default)]
+    pub provenance: HashMap<String, Value>,
+
+    #[serde(rename = "type")]
+    pub typ: String,
+}
+
+impl ResultCollection {
+    /// read a [ResultCollection] from the JSON file at `path`
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let s = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&s)?)
+    }
+
+    /// return the [CollectionType] corresponding to the declared `type` of the
+    /// collection, checking that every entry has the matching entry type
+    pub fn collection_type(&self) -> Result<CollectionType, String> {
+        let typ = CollectionType::from_result_collection_type(&self.typ)
+            .ok_or_else(|| {
+                format!("unrecognized ResultCollection type: `{}`", self.typ)
+            })?;
+        for entry in self.entries.values().flatten() {
+            if entry.typ != typ.entry_type() {
+                return Err(format!(
+                    "entry `{}` has type `{}`, but the entries in a {} \
+                     should have type `{}`",
+                    entry.record_id,
+                    entry.typ,
+                    self.typ,
+                    typ.entry_type()
+                ));
+            }
+        }
+        Ok(typ)
+    }
+
+    /// construct a [ResultCollection] of `collection_type` from the records in
+    /// `response`, which was retrieved from the server at `address`. records
+    /// appearing in multiple datasets are only included once. datasets listing
+    /// their molecules instead of their records don't contribute any entries,
+    /// so use [Self::from_records] with the retrieved records for those
+    pub fn new(
+        collection_type: CollectionType,
+        address: &str,
+        response: &CollectionGetResponse,
+    ) -> Self {
+        let records = response
+            .data
+            .iter()
+            .filter_map(|ds| match &ds.records {
+                Records::Map(m) => Some(m.values()),
+                _ => None,
+            })
+            .flatten()
+            .map(|rec| {
+                (
+                    rec.record_id().clone(),
+                    rec.cmiles().clone(),
+                    rec.fixed_hydrogen_inchi_key().clone(),
+                )
+            });
+        Self::from_records(collection_type, address, records)
+    }
+
+    /// construct a [ResultCollection] of `collection_type` from the
+    /// `(record_id, cmiles, inchi_key)` of each record retrieved from the
+    /// server at `address`. records appearing more than once are only
+    /// included once
+    pub fn from_records(
+        collection_type: CollectionType,
+        address: &str,
+        records: impl IntoIterator<Item = (String, String, String)>,
+    ) -> Self {
+        let mut seen = HashSet::new();
+        let mut entries = Vec::new();
+        for (record_id, cmiles, inchi_key) in records {
+            if !seen.insert(record_id.clone()) {
+                continue;
+            }
+            entries.push(CollectionEntry {
+                typ: collection_type.entry_type().to_owned(),
+                record_id,
+                cmiles,
+                inchi_key,
+            });
+        }
+        entries.sort_by(|a, b| a.record_id.cmp(&b.record_id));
+        Self {
+            entries: BTreeMap::from([(address.to_owned(), entries)]),
+            provenance: HashMap::new(),
+            typ: collection_type.result_collection_type().to_owned(),
+        }
+    }
+
+    /// only keep the entries whose record ids are in `ids`
+    pub fn retain_records<'a>(
+        &mut self,
+        ids: impl IntoIterator<Item = &'a String>,
+    ) {
+        let ids: HashSet<_> = ids.into_iter().collect();
+        for entries in self.entries.values_mut() {
+            entries.retain(|e| ids.contains(&e.record_id));
+        }
+    }
+}
+
+/// split the entries in `value` by the address of the server they came from,
+/// returning a vector of (address, collection) pairs sorted by address. unlike
+/// the [From] implementation for [TorsionDriveResultCollection], which
+/// combines all of the servers, this allows each group to be requested from
+/// the right server
+pub fn group_by_server(
+    value: ResultCollection,
+) -> Vec<(String, CollectionGetResponse)> {
+    value
+        .entries
+        .into_iter()
+        .map(|(address, entries)| {
+            let entries = entries
+                .into_iter()
+                .map(|v| (v.record_id, v.cmiles, v.inchi_key));
+            (address, CollectionGetResponse::from_entries(entries))
+        })
+        .collect()
+}
+
+impl From<TorsionDriveResultCollection> for CollectionGetResponse {
+    fn from(value: TorsionDriveResultCollection) -> Self {
+        Self::from_entries(
+            value
+                .entries
+                .into_values()
+                .flatten()
+                .map(|v| (v.record_id, v.cmiles, v.inchi_key)),
+        )
+    }
+}
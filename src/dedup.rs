@@ -0,0 +1,244 @@
+//! Collapsing duplicate [ResultEntry]s, which are common when combining
+//! datasets.
+
+use std::{collections::HashMap, str::FromStr};
+
+use tracing::{info, warn};
+
+use crate::{procedure::Record, ResultEntry};
+
+/// What to consider a duplicate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupKey {
+    /// entries with the same InChI key
+    InchiKey,
+
+    /// entries with the same cmiles and, for torsion drives, the same
+    /// dihedrals
+    Cmiles,
+}
+
+impl FromStr for DedupKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inchi" => Ok(Self::InchiKey),
+            "cmiles" => Ok(Self::Cmiles),
+            e => Err(format!("unmatched DedupKey: `{e}`")),
+        }
+    }
+}
+
+/// Which of a set of duplicates to keep
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DedupPolicy {
+    /// keep the first entry in the input order
+    #[default]
+    First,
+
+    /// keep the entry with the lowest [Record::energy]. entries without an
+    /// energy are only kept if none of their duplicates have one
+    LowestEnergy,
+}
+
+impl FromStr for DedupPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "lowest-energy" => Ok(Self::LowestEnergy),
+            e => Err(format!("unmatched DedupPolicy: `{e}`")),
+        }
+    }
+}
+
+/// put a dihedral in a canonical direction so that (a, b, c, d) and
+/// (d, c, b, a) compare equal
+fn canonical_dihedral(
+    (a, b, c, d): (usize, usize, usize, usize),
+) -> (usize, usize, usize, usize) {
+    (a, b, c, d).min((d, c, b, a))
+}
+
+/// the key to group `entry` by, or `None` if the identifier it's built from is
+/// empty and so can't tell the entry apart from others
+fn key<R: Record>(entry: &ResultEntry<R>, key: DedupKey) -> Option<String> {
+    let id = match key {
+        DedupKey::InchiKey => &entry.inchi_key,
+        DedupKey::Cmiles => &entry.cmiles,
+    };
+    if id.is_empty() {
+        return None;
+    }
+    Some(match key {
+        DedupKey::InchiKey => entry.inchi_key.clone(),
+        DedupKey::Cmiles => match entry.record.dihedrals() {
+            Some(dihedrals) => {
+                let mut dihedrals: Vec<_> =
+                    dihedrals.iter().copied().map(canonical_dihedral).collect();
+                dihedrals.sort();
+                format!("{} {dihedrals:?}", entry.cmiles)
+            }
+            None => entry.cmiles.clone(),
+        },
+    })
+}
+
+/// collapse the entries in `entries` that share a `key`, keeping one from each
+/// group according to `policy`. the kept entries are in the order their groups
+/// first appeared. entries with an empty key are never grouped but kept with a
+/// warning. prints a summary and returns the number of entries removed
+pub fn deduplicate<R: Record>(
+    entries: &mut Vec<ResultEntry<R>>,
+    key_type: DedupKey,
+    policy: DedupPolicy,
+) -> usize {
+    let before = entries.len();
+    let mut groups: HashMap<String, usize> = HashMap::new();
+    let mut ret: Vec<ResultEntry<R>> = Vec::new();
+    for entry in entries.drain(..) {
+        let Some(k) = key(&entry, key_type) else {
+            warn!(
+                "keeping record {} with no {key_type:?} to deduplicate by",
+                entry.record.id()
+            );
+            ret.push(entry);
+            continue;
+        };
+        let Some(&i) = groups.get(&k) else {
+            groups.insert(k, ret.len());
+            ret.push(entry);
+            continue;
+        };
+        if policy == DedupPolicy::LowestEnergy {
+            let replace = match (entry.record.energy(), ret[i].record.energy())
+            {
+                (Some(new), Some(old)) => new < old,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if replace {
+                ret[i] = entry;
+            }
+        }
+    }
+    *entries = ret;
+    let removed = before - entries.len();
+    info!(
+        "deduplicate({key_type:?}, {policy:?}): collapsed {before} entries \
+         into {}, removing {removed}",
+        entries.len()
+    );
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::procedure::QCSpecification;
+
+    use super::*;
+
+    struct Fake {
+        id: String,
+        energy: Option<f64>,
+        dihedrals: Vec<(usize, usize, usize, usize)>,
+        spec: QCSpecification,
+    }
+
+    impl Record for Fake {
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn initial_molecules(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn energy(&self) -> Option<f64> {
+            self.energy
+        }
+
+        fn dihedrals(&self) -> Option<&[(usize, usize, usize, usize)]> {
+            Some(&self.dihedrals)
+        }
+
+        fn qc_spec(&self) -> &QCSpecification {
+            &self.spec
+        }
+
+        fn scale_energies(&mut self, factor: f64) {
+            self.energy = self.energy.map(|e| e * factor);
+        }
+    }
+
+    fn entry(
+        id: &str,
+        cmiles: &str,
+        inchi_key: &str,
+        energy: Option<f64>,
+        dihedral: (usize, usize, usize, usize),
+    ) -> ResultEntry<Fake> {
+        ResultEntry {
+            record: Fake {
+                id: id.to_owned(),
+                energy,
+                dihedrals: vec![dihedral],
+                spec: QCSpecification::default(),
+            },
+            cmiles: cmiles.to_owned(),
+            inchi_key: inchi_key.to_owned(),
+            molecules: Vec::new(),
+            initial_molecules: Vec::new(),
+            dataset: None,
+        }
+    }
+
+    fn entries() -> Vec<ResultEntry<Fake>> {
+        vec![
+            entry("1", "CCO", "A", Some(-1.0), (0, 1, 2, 3)),
+            entry("2", "CCO", "A", Some(-2.0), (3, 2, 1, 0)),
+            entry("3", "CCO", "A", None, (1, 2, 3, 4)),
+            entry("4", "OCC", "A", Some(-3.0), (0, 1, 2, 3)),
+            entry("5", "CCN", "B", Some(-1.0), (0, 1, 2, 3)),
+        ]
+    }
+
+    fn ids(entries: &[ResultEntry<Fake>]) -> Vec<&str> {
+        entries.iter().map(|e| e.record.id()).collect()
+    }
+
+    #[test]
+    fn dedup() {
+        use DedupKey::*;
+        use DedupPolicy::*;
+        let tests = [
+            (InchiKey, First, vec!["1", "5"]),
+            (InchiKey, LowestEnergy, vec!["4", "5"]),
+            (Cmiles, First, vec!["1", "3", "4", "5"]),
+            (Cmiles, LowestEnergy, vec!["2", "3", "4", "5"]),
+        ];
+        for (key, policy, want) in tests {
+            let mut got = entries();
+            let removed = deduplicate(&mut got, key, policy);
+            assert_eq!(ids(&got), want, "{key:?}, {policy:?}");
+            assert_eq!(removed, 5 - want.len());
+        }
+    }
+
+    #[test]
+    fn empty_keys() {
+        use DedupKey::*;
+        for key in [InchiKey, Cmiles] {
+            let mut got = entries();
+            got.extend([
+                entry("6", "", "", Some(-1.0), (0, 1, 2, 3)),
+                entry("7", "", "", Some(-2.0), (0, 1, 2, 3)),
+            ]);
+            deduplicate(&mut got, key, DedupPolicy::LowestEnergy);
+            let got = ids(&got);
+            assert_eq!(got[got.len() - 2..], ["6", "7"], "{key:?}");
+        }
+    }
+}
@@ -0,0 +1,483 @@
+//! Filters for removing [ResultEntry]s from the output, like the results
+//! filters in qcsubmit. Each filter implements [Filter], and [apply] runs one
+//! over a vector of entries.
+
+use std::{collections::HashSet, str::FromStr};
+
+use tracing::{info, warn};
+
+use crate::{
+    procedure::{Driver, QCSpecification, Record},
+    smiles,
+    validate::check_dihedrals,
+    ResultEntry,
+};
+
+pub trait Filter<R> {
+    /// a short description of the filter for reporting
+    fn name(&self) -> String;
+
+    /// return `true` if `entry` should be kept
+    fn keep(&self, entry: &ResultEntry<R>) -> bool;
+}
+
+/// remove the entries in `entries` rejected by `filter`, printing and
+/// returning the number of entries removed
+pub fn apply<R>(
+    entries: &mut Vec<ResultEntry<R>>,
+    filter: &impl Filter<R>,
+) -> usize {
+    let before = entries.len();
+    entries.retain(|e| filter.keep(e));
+    let removed = before - entries.len();
+    info!("{}: removed {removed} of {before} entries", filter.name());
+    removed
+}
+
+/// Selects records by their [QCSpecification]. Unlike the [Filter]s, this
+/// operates on the records themselves, so it can be applied before requesting
+/// any of their molecules. Each of the fields is compared case-insensitively,
+/// and a `None` field matches anything
+#[derive(Clone, Debug, Default)]
+pub struct SpecFilter {
+    pub method: Option<String>,
+
+    /// `none` matches records without a basis set
+    pub basis: Option<String>,
+    pub program: Option<String>,
+    pub driver: Option<Driver>,
+}
+
+impl SpecFilter {
+    /// whether the filter matches every record
+    pub fn is_empty(&self) -> bool {
+        self.method.is_none()
+            && self.basis.is_none()
+            && self.program.is_none()
+            && self.driver.is_none()
+    }
+
+    pub fn name(&self) -> String {
+        let fields = [
+            ("method", &self.method),
+            ("basis", &self.basis),
+            ("program", &self.program),
+        ];
+        let mut fields: Vec<_> = fields
+            .into_iter()
+            .filter_map(|(k, v)| v.as_ref().map(|v| format!("{k}={v}")))
+            .collect();
+        if let Some(driver) = self.driver {
+            fields.push(format!("driver={driver:?}"));
+        }
+        format!("SpecFilter({})", fields.join(", "))
+    }
+
+    /// return `true` if `spec` matches the filter
+    pub fn matches(&self, spec: &QCSpecification) -> bool {
+        let eq = |want: &Option<String>, got: &str| {
+            want.as_ref().is_none_or(|w| w.eq_ignore_ascii_case(got))
+        };
+        eq(&self.method, &spec.method)
+            && eq(&self.basis, spec.basis.as_deref().unwrap_or("none"))
+            && eq(&self.program, &spec.program)
+            && self.driver.is_none_or(|d| d == spec.driver)
+    }
+
+    /// remove the records in `records` whose `qc_spec` doesn't match the
+    /// filter, printing and returning the number of records removed
+    pub fn apply<R: Record>(&self, records: &mut Vec<R>) -> usize {
+        let before = records.len();
+        records.retain(|r| self.matches(r.qc_spec()));
+        let removed = before - records.len();
+        info!("{}: removed {removed} of {before} records", self.name());
+        removed
+    }
+}
+
+/// convert an element symbol to title case, the form used in QCSchema
+/// molecules, so that `CL`, `cl`, and `Cl` are all treated the same
+fn normalize_symbol(s: &str) -> String {
+    let mut chars = s.trim().chars();
+    match chars.next() {
+        Some(c) => c
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Analogous to qcsubmit's `ElementFilter`. Only keeps entries whose molecules
+/// contain no elements outside of the allowed set
+pub struct ElementFilter {
+    allowed: HashSet<String>,
+}
+
+impl ElementFilter {
+    /// construct an [ElementFilter] allowing the element symbols in `allowed`
+    pub fn new<S: AsRef<str>>(allowed: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            allowed: allowed
+                .into_iter()
+                .map(|s| normalize_symbol(s.as_ref()))
+                .collect(),
+        }
+    }
+}
+
+impl<R> Filter<R> for ElementFilter {
+    fn name(&self) -> String {
+        let mut allowed: Vec<_> = self.allowed.iter().cloned().collect();
+        allowed.sort();
+        format!("ElementFilter({})", allowed.join(","))
+    }
+
+    fn keep(&self, entry: &ResultEntry<R>) -> bool {
+        entry
+            .molecules
+            .iter()
+            .flat_map(|m| &m.symbols)
+            .all(|s| self.allowed.contains(&normalize_symbol(s)))
+    }
+}
+
+/// Analogous to qcsubmit's `ConnectivityFilter`. Removes entries where the
+/// bonds guessed from the geometry of any of the molecules differ from the
+/// bonds in the cmiles. This includes the initial molecules if they have been
+/// retrieved
+pub struct ConnectivityFilter {
+    tolerance: f64,
+}
+
+impl ConnectivityFilter {
+    /// construct a [ConnectivityFilter] with `tolerance`. see
+    /// [Molecule::guess_connectivity] for how this is used
+    ///
+    /// [Molecule::guess_connectivity]:
+    /// crate::molecule::Molecule::guess_connectivity
+    pub fn new(tolerance: f64) -> Self {
+        Self { tolerance }
+    }
+}
+
+impl Default for ConnectivityFilter {
+    /// the same default tolerance as qcsubmit
+    fn default() -> Self {
+        Self::new(1.2)
+    }
+}
+
+impl<R> Filter<R> for ConnectivityFilter {
+    fn name(&self) -> String {
+        format!("ConnectivityFilter({})", self.tolerance)
+    }
+
+    fn keep(&self, entry: &ResultEntry<R>) -> bool {
+        let want =
+            match smiles::parse(&entry.cmiles).and_then(|s| s.mapped_bonds()) {
+                Ok(bonds) => bonds,
+                Err(e) => {
+                    warn!("failed to read bonds from `{}`: {e}", entry.cmiles);
+                    return false;
+                }
+            };
+        entry
+            .molecules
+            .iter()
+            .chain(&entry.initial_molecules)
+            .all(|m| {
+                m.guess_connectivity(self.tolerance)
+                    .is_ok_and(|got| got == want)
+            })
+    }
+}
+
+/// Removes the conformers of torsion drive entries whose driven dihedral
+/// differs from its grid angle by more than a tolerance in degrees, along with
+/// their grid points in the record. Unlike the [Filter]s, this edits the
+/// entries rather than just removing them, so it has its own `apply`. Entries
+/// for other kinds of records are always kept
+pub struct DihedralFilter {
+    tolerance: f64,
+}
+
+impl DihedralFilter {
+    pub fn new(tolerance: f64) -> Self {
+        Self { tolerance }
+    }
+}
+
+impl DihedralFilter {
+    pub fn name(&self) -> String {
+        format!("DihedralFilter({})", self.tolerance)
+    }
+
+    /// remove the bad conformers and grid points from `entries`, and any
+    /// entries left without conformers. prints and returns the number of
+    /// conformers removed
+    pub fn apply<R: Record>(&self, entries: &mut Vec<ResultEntry<R>>) -> usize {
+        let before = entries.len();
+        let mut removed = 0;
+        entries.retain_mut(|entry| {
+            let bad = check_dihedrals(
+                &entry.record,
+                entry.conformers(),
+                self.tolerance,
+            );
+            if bad.is_empty() {
+                return true;
+            }
+            for d in &bad {
+                warn!(
+                    "record {} at {}: dihedral {:?} is {:.1}°",
+                    d.record_id, d.grid_id, d.dihedral, d.actual
+                );
+            }
+            let bad: HashSet<String> =
+                bad.into_iter().map(|d| d.grid_id).collect();
+            // the conformers are in the order of the grid points
+            let grid_ids: Vec<String> = entry
+                .record
+                .grid_points()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(g, _)| g.to_owned())
+                .collect();
+            let mut keep = grid_ids.iter().map(|g| !bad.contains(g));
+            entry.molecules.retain(|_| keep.next().unwrap_or(true));
+            for grid_id in &bad {
+                entry.record.remove_grid_point(grid_id);
+            }
+            removed += bad.len();
+            !entry.molecules.is_empty()
+        });
+        info!(
+            "{}: removed {removed} conformers and {} of {before} entries",
+            self.name(),
+            before - entries.len()
+        );
+        removed
+    }
+}
+
+/// An inclusive range of total charges, parsed from either a single charge
+/// like `0` or a range like `-1..1`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChargeRange {
+    pub min: isize,
+    pub max: isize,
+}
+
+impl FromStr for ChargeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |c: &str| {
+            c.trim()
+                .parse::<isize>()
+                .map_err(|e| format!("invalid charge `{c}`: {e}"))
+        };
+        let (min, max) = match s.split_once("..") {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if min > max {
+            return Err(format!("empty charge range `{s}`"));
+        }
+        Ok(Self { min, max })
+    }
+}
+
+/// Only keeps entries whose molecules have a total charge in a [ChargeRange]
+pub struct ChargeFilter {
+    range: ChargeRange,
+}
+
+impl ChargeFilter {
+    pub fn new(range: ChargeRange) -> Self {
+        Self { range }
+    }
+}
+
+impl<R> Filter<R> for ChargeFilter {
+    fn name(&self) -> String {
+        let ChargeRange { min, max } = self.range;
+        format!("ChargeFilter({min}..{max})")
+    }
+
+    fn keep(&self, entry: &ResultEntry<R>) -> bool {
+        entry.molecules.iter().all(|m| {
+            // molecular_charge is a float, but only integer charges make
+            // sense here
+            let c = m.molecular_charge.round() as isize;
+            (self.range.min..=self.range.max).contains(&c)
+        })
+    }
+}
+
+/// Only keeps entries whose molecules have one of the allowed multiplicities
+pub struct MultiplicityFilter {
+    allowed: HashSet<usize>,
+}
+
+impl MultiplicityFilter {
+    pub fn new(allowed: impl IntoIterator<Item = usize>) -> Self {
+        Self {
+            allowed: allowed.into_iter().collect(),
+        }
+    }
+}
+
+impl<R> Filter<R> for MultiplicityFilter {
+    fn name(&self) -> String {
+        let mut allowed: Vec<_> =
+            self.allowed.iter().map(usize::to_string).collect();
+        allowed.sort();
+        format!("MultiplicityFilter({})", allowed.join(","))
+    }
+
+    fn keep(&self, entry: &ResultEntry<R>) -> bool {
+        entry
+            .molecules
+            .iter()
+            .all(|m| self.allowed.contains(&m.molecular_multiplicity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use crate::{
+        molecule::Molecule,
+        procedure::{OptimizationRecord, Response},
+    };
+
+    use super::*;
+
+    /// load the molecules from testfiles/molecules.json and wrap each of them
+    /// in an otherwise empty [ResultEntry]
+    fn load_entries() -> Vec<ResultEntry<()>> {
+        let s = read_to_string("testfiles/molecules.json").unwrap();
+        let c: Response<Molecule> = serde_json::from_str(&s).unwrap();
+        // the molecules record their own cmiles in their extras
+        let v: serde_json::Value = serde_json::from_str(&s).unwrap();
+        let extras = v["data"].as_array().unwrap().iter().map(|m| {
+            m["extras"]["canonical_isomeric_explicit_hydrogen_mapped_smiles"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        });
+        c.into_iter()
+            .zip(extras)
+            .map(|(m, cmiles)| ResultEntry {
+                record: (),
+                cmiles,
+                inchi_key: String::new(),
+                molecules: vec![m],
+                initial_molecules: Vec::new(),
+                dataset: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn element_filter() {
+        let mut entries = load_entries();
+        let before = entries.len();
+        let has_sulfur = entries
+            .iter()
+            .filter(|e| e.molecules[0].symbols.iter().any(|s| s == "S"))
+            .count();
+        assert!(has_sulfur > 0);
+
+        let removed = apply(
+            &mut entries,
+            &ElementFilter::new(["C", "H", "n", "O", "F", "P", "CL", "Br"]),
+        );
+        assert_eq!(removed, has_sulfur);
+        assert_eq!(entries.len(), before - has_sulfur);
+    }
+
+    #[test]
+    fn connectivity_filter() {
+        let mut entries = load_entries();
+        // some of the optimizations in these torsion drives broke the N-N
+        // bond in sulfonyl hydrazides, leaving the N atoms ~2.1 Å apart
+        let removed = apply(&mut entries, &ConnectivityFilter::default());
+        assert_eq!(removed, 36);
+        let before = entries.len();
+
+        // pull the first atom of the first molecule far away, breaking its
+        // bonds
+        entries[0].molecules[0].geometry[0] += 10.0;
+        let removed = apply(&mut entries, &ConnectivityFilter::default());
+        assert_eq!(removed, 1);
+        assert_eq!(entries.len(), before - 1);
+    }
+
+    #[test]
+    fn spec_filter() {
+        let s = read_to_string("testfiles/opt_procedure.json").unwrap();
+        let c: Response<OptimizationRecord> = serde_json::from_str(&s).unwrap();
+        let mut records = c.data;
+        let before = records.len();
+
+        let matching = SpecFilter {
+            method: Some("B3LYP-D3BJ".to_owned()),
+            basis: Some("dzvp".to_owned()),
+            program: None,
+            driver: Some(Driver::Gradient),
+        };
+        assert_eq!(matching.apply(&mut records), 0);
+
+        let wrong_basis = SpecFilter {
+            basis: Some("none".to_owned()),
+            ..matching
+        };
+        assert_eq!(wrong_basis.apply(&mut records), before);
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn charge_range() {
+        let tests = [
+            ("0", Ok((0, 0))),
+            ("-1..1", Ok((-1, 1))),
+            ("-2..-1", Ok((-2, -1))),
+            ("1..-1", Err(())),
+            ("x", Err(())),
+        ];
+        for (s, want) in tests {
+            let got = s.parse::<ChargeRange>().map(|r| (r.min, r.max));
+            assert_eq!(got.map_err(|_| ()), want, "{s}");
+        }
+    }
+
+    #[test]
+    fn charge_multiplicity_filters() {
+        let mut entries = load_entries();
+        let count = |entries: &[ResultEntry<()>], charge: f64| {
+            entries
+                .iter()
+                .filter(|e| e.molecules[0].molecular_charge == charge)
+                .count()
+        };
+        let (anions, cations) = (count(&entries, -1.0), count(&entries, 1.0));
+        assert!(anions > 0 && cations > 0);
+        entries[0].molecules[0].molecular_charge = 2.0;
+        let charged =
+            count(&entries, 2.0) + count(&entries, -1.0) + count(&entries, 1.0);
+
+        let wide = ChargeFilter::new("-1..1".parse().unwrap());
+        assert_eq!(apply(&mut entries, &wide), 1);
+        let neutral = ChargeFilter::new("0".parse().unwrap());
+        assert_eq!(apply(&mut entries, &neutral), charged - 1);
+
+        // the test molecules are all singlets
+        entries[0].molecules[0].molecular_multiplicity = 3;
+        assert_eq!(apply(&mut entries, &MultiplicityFilter::new([1])), 1);
+        assert_eq!(apply(&mut entries, &MultiplicityFilter::new([1, 3])), 0);
+    }
+}
@@ -0,0 +1,130 @@
+//! [FractalClient] queries for the key-value store holding the `stdout`,
+//! `stderr`, and `error` blobs attached to records.
+//!
+//! [FractalClient]: crate::client::FractalClient
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{client::Body, procedure::Meta};
+
+#[derive(Default, Serialize)]
+struct QueryFilter {}
+
+#[derive(Serialize)]
+struct Data {
+    id: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct KVStoreGetBody {
+    meta: QueryFilter,
+    data: Data,
+}
+
+impl Body for KVStoreGetBody {
+    fn new(id: Vec<String>) -> Self {
+        Self {
+            meta: QueryFilter::default(),
+            data: Data { id },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Bzip2,
+    Lzma,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KVStore {
+    #[serde(default)]
+    pub compression: Compression,
+
+    #[serde(default)]
+    pub compression_level: i32,
+
+    /// older servers call this field `value`
+    #[serde(alias = "value")]
+    pub data: Value,
+}
+
+impl KVStore {
+    /// return the stored data as a string, or `None` if it is compressed. I
+    /// haven't seen compressed data come back over JSON, so decompression
+    /// isn't supported yet
+    pub fn as_string(&self) -> Option<String> {
+        if self.compression != Compression::None {
+            return None;
+        }
+        match &self.data {
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        }
+    }
+
+    /// try to interpret the stored data as a [ComputeError], which is how
+    /// QCFractal stores the `error` field of a failed record
+    pub fn as_compute_error(&self) -> Option<ComputeError> {
+        match &self.data {
+            Value::String(s) => serde_json::from_str(s).ok(),
+            v => serde_json::from_value(v.clone()).ok(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ComputeError {
+    pub error_type: String,
+    pub error_message: String,
+}
+
+/// unlike the other endpoints, `kvstore` returns a map of id -> [KVStore]
+/// instead of a list
+#[derive(Debug, Deserialize)]
+pub struct KVStoreResponse {
+    pub meta: Meta,
+    pub data: HashMap<String, KVStore>,
+}
+
+impl IntoIterator for KVStoreResponse {
+    type Item = (String, KVStore);
+
+    type IntoIter = std::collections::hash_map::IntoIter<String, KVStore>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn de_kvstore() {
+        let s = r#"{
+          "meta": {"errors": [], "success": true, "missing": [], "n_found": 2},
+          "data": {
+            "1": {
+              "compression": "none",
+              "compression_level": 0,
+              "data": "{\"error_type\": \"unknown_error\", \"error_message\": \"Could not converge SCF iterations in 100 iterations.\"}"
+            },
+            "2": {"value": "some stdout"}
+          }
+        }"#;
+        let c: KVStoreResponse = serde_json::from_str(s).unwrap();
+        let err = c.data["1"].as_compute_error().unwrap();
+        assert_eq!(err.error_type, "unknown_error");
+        assert_eq!(c.data["2"].as_string().unwrap(), "some stdout");
+        assert!(c.data["2"].as_compute_error().is_none());
+    }
+}
@@ -2,13 +2,32 @@ use std::collections::HashMap;
 
 use collection::TorsionDriveResult;
 use molecule::Molecule;
-use procedure::{OptimizationRecord, TorsionDriveRecord};
-use serde::{Deserialize, Serialize};
+use procedure::{
+    GridOptimizationRecord, OptimizationRecord, TorsionDriveRecord,
+};
+use result::ResultRecord;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::warn;
+use units::{EnergyUnit, LengthUnit};
 
+pub mod chunking;
 pub mod client;
 pub mod collection;
+pub mod dedup;
+pub mod elements;
+pub mod filter;
+pub mod kvstore;
+pub mod mapping;
+pub mod metrics;
 pub mod molecule;
 pub mod procedure;
+pub mod progress;
+pub mod reaction;
+pub mod report;
+pub mod result;
+pub mod smiles;
+pub mod units;
+pub mod validate;
 
 #[cfg(test)]
 mod tests;
@@ -31,44 +50,232 @@ impl Status {
     }
 }
 
-/// constructs output usable by qcsubmit. Returns a vector of (record_id,
-/// cmiles, Vec<geometry>), where a geometry is a Vec<f64> to be inserted in a
-/// Molecule._conformers. There's not actually code in qcsubmit to do this
-/// directly, but see results/caching.py:cached_query_torsion_drive_results for
-/// how to reconstruct its output
+/// Information about the output as a whole
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Metadata {
+    pub length_unit: LengthUnit,
+    pub energy_unit: EnergyUnit,
+}
+
+/// The full output of `get` and `convert`: the [ResultEntry]s and metadata
+/// describing them
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Output<T> {
+    pub metadata: Metadata,
+    pub results: Vec<T>,
+}
+
+/// A single entry in the output: a record, the mapped SMILES from its
+/// collection entry, and the molecules for its conformers. For TorsionDrives
+/// there is one conformer per grid point, sorted by grid id, while
+/// Optimizations only have their final geometry
+#[derive(Debug, Serialize)]
+pub struct ResultEntry<R> {
+    pub record: R,
+    pub cmiles: String,
+
+    /// the fixed-hydrogen InChI key from the collection entry if available,
+    /// otherwise the standard InChI key
+    pub inchi_key: String,
+
+    /// the full molecules are kept around for filtering, but only their
+    /// geometries are included in the output
+    #[serde(rename = "conformers", serialize_with = "serialize_geometries")]
+    pub molecules: Vec<Molecule>,
+
+    /// the initial molecules of the record. these are only retrieved when
+    /// needed, by [FractalClient::initial_molecules], and only their
+    /// geometries are included in the output, when they were requested
+    ///
+    /// [FractalClient::initial_molecules]: client::FractalClient::initial_molecules
+    #[serde(
+        rename = "initial_conformers",
+        serialize_with = "serialize_geometries",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub initial_molecules: Vec<Molecule>,
+
+    /// the dataset this entry was taken from. when the same record appears in
+    /// multiple datasets, this is the first of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>,
+}
+
+impl<R> ResultEntry<R> {
+    /// return an iterator over the geometries of the conformers in `self`
+    pub fn conformers(&self) -> impl Iterator<Item = &Vec<f64>> {
+        self.molecules.iter().map(|m| &m.geometry)
+    }
+}
+
+/// A borrowed view of a [ResultEntry] that serializes its full QCSchema
+/// molecules under `molecules` instead of just their geometries under
+/// `conformers`, for multi-fragment systems and anything else needing more
+/// than the cmiles and geometry to reconstruct
+#[derive(Debug, Serialize)]
+pub struct FullResultEntry<'a, R> {
+    pub record: &'a R,
+    pub cmiles: &'a str,
+    pub inchi_key: &'a str,
+    pub molecules: &'a [Molecule],
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub initial_molecules: &'a Vec<Molecule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: &'a Option<String>,
+}
+
+impl<R> ResultEntry<R> {
+    /// return a view of `self` that serializes its full molecules
+    pub fn full(&self) -> FullResultEntry<'_, R> {
+        FullResultEntry {
+            record: &self.record,
+            cmiles: &self.cmiles,
+            inchi_key: &self.inchi_key,
+            molecules: &self.molecules,
+            initial_molecules: &self.initial_molecules,
+            dataset: &self.dataset,
+        }
+    }
+}
+
+fn serialize_geometries<S: Serializer>(
+    molecules: &[Molecule],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(molecules.iter().map(|m| &m.geometry))
+}
+
+/// build a map of record_id -> result from `results`. there may be more
+/// results than records, but accessing them with this map by the id stored on
+/// the records ensures that I only get the ones I want. if a record appears
+/// more than once, the first result wins
+fn cmiles_map(
+    results: &[TorsionDriveResult],
+) -> HashMap<&String, &TorsionDriveResult> {
+    let mut ret = HashMap::new();
+    for rec in results {
+        ret.entry(rec.record_id()).or_insert(rec);
+    }
+    ret
+}
+
+/// look up the final molecule at each of `grid_ids` of the record with id
+/// `record_id`, going through the (record_id, grid_id) -> molecule_id map
+/// `molecule_ids`. returns `None` after logging a warning if any of them
+/// couldn't be retrieved
+fn grid_molecules(
+    record_id: &String,
+    grid_ids: Vec<&String>,
+    molecule_ids: &HashMap<(String, String), String>,
+    molecules: &HashMap<String, Molecule>,
+) -> Option<Vec<Molecule>> {
+    let mut ret = Vec::with_capacity(grid_ids.len());
+    for grid_id in grid_ids {
+        let key = (record_id.clone(), grid_id.clone());
+        let Some(mol) = molecule_ids.get(&key).and_then(|i| molecules.get(i))
+        else {
+            warn!(
+                "skipping record {record_id}: missing molecule for grid point \
+                 {grid_id}"
+            );
+            return None;
+        };
+        ret.push(mol.clone());
+    }
+    Some(ret)
+}
+
+/// look up the collection entry for `record_id` in `cmiles_map`, logging a
+/// warning if there isn't one
+fn collection_entry<'a>(
+    cmiles_map: &HashMap<&String, &'a TorsionDriveResult>,
+    record_id: &String,
+) -> Option<&'a TorsionDriveResult> {
+    let ret = cmiles_map.get(record_id).copied();
+    if ret.is_none() {
+        warn!("skipping record {record_id}: not found in the collection");
+    }
+    ret
+}
+
+/// constructs output usable by qcsubmit. Returns a vector of [ResultEntry]s,
+/// where a geometry is a Vec<f64> to be inserted in a Molecule._conformers.
+/// There's not actually code in qcsubmit to do this directly, but see
+/// results/caching.py:cached_query_torsion_drive_results for how to
+/// reconstruct its output
 pub fn make_td_results(
     results: Vec<TorsionDriveResult>,
     records: Vec<TorsionDriveRecord>,
     molecule_ids: HashMap<(String, String), String>,
     molecules: HashMap<String, Molecule>,
-) -> Vec<(TorsionDriveRecord, String, Vec<Vec<f64>>)> {
-    // there may be more results than records, but accessing them with this map
-    // by the id stored on the records ensures that I only get the ones I want
-    let cmiles_map: HashMap<_, _> = results
-        .iter()
-        .map(|rec| (rec.record_id(), rec.cmiles()))
-        .collect();
+) -> Vec<ResultEntry<TorsionDriveRecord>> {
+    let cmiles_map = cmiles_map(&results);
 
     let mut ret = Vec::new();
     for record in records {
-        let mut grid_ids: Vec<_> = record.minimum_positions.keys().collect();
-        grid_ids.sort_by_key(|g| {
-            let x: &[_] = &['[', ']'];
-            g.trim_matches(x).parse::<isize>().unwrap()
+        let grid_ids = match record.sorted_grid_ids() {
+            Ok(g) => g,
+            Err(e) => {
+                warn!("skipping record {}: {e}", record.id);
+                continue;
+            }
+        };
+        let Some(qc_grid_molecules) =
+            grid_molecules(&record.id, grid_ids, &molecule_ids, &molecules)
+        else {
+            continue;
+        };
+        let Some(result) = collection_entry(&cmiles_map, &record.id) else {
+            continue;
+        };
+        ret.push(ResultEntry {
+            cmiles: result.cmiles().clone(),
+            inchi_key: result.fixed_hydrogen_inchi_key().clone(),
+            dataset: result.dataset.clone(),
+            record,
+            molecules: qc_grid_molecules,
+            initial_molecules: Vec::new(),
         });
+    }
 
-        let mut qc_grid_molecules = Vec::new();
-        for grid_id in &grid_ids {
-            let i = &molecule_ids[&(record.id.clone(), (*grid_id).clone())];
-            qc_grid_molecules.push(molecules[i].clone());
-        }
+    ret
+}
 
-        let cmiles = cmiles_map[&record.id].clone();
-        ret.push((
+/// Analogous to [make_td_results] for [GridOptimizationRecord]s, with one
+/// conformer per grid point, sorted by grid id
+pub fn make_go_results(
+    results: Vec<TorsionDriveResult>,
+    records: Vec<GridOptimizationRecord>,
+    molecule_ids: HashMap<(String, String), String>,
+    molecules: HashMap<String, Molecule>,
+) -> Vec<ResultEntry<GridOptimizationRecord>> {
+    let cmiles_map = cmiles_map(&results);
+
+    let mut ret = Vec::new();
+    for record in records {
+        let grid_ids = match record.sorted_grid_ids() {
+            Ok(g) => g,
+            Err(e) => {
+                warn!("skipping record {}: {e}", record.id);
+                continue;
+            }
+        };
+        let Some(grid_molecules) =
+            grid_molecules(&record.id, grid_ids, &molecule_ids, &molecules)
+        else {
+            continue;
+        };
+        let Some(result) = collection_entry(&cmiles_map, &record.id) else {
+            continue;
+        };
+        ret.push(ResultEntry {
+            cmiles: result.cmiles().clone(),
+            inchi_key: result.fixed_hydrogen_inchi_key().clone(),
+            dataset: result.dataset.clone(),
             record,
-            cmiles,
-            qc_grid_molecules.into_iter().map(|m| m.geometry).collect(),
-        ));
+            molecules: grid_molecules,
+            initial_molecules: Vec::new(),
+        });
     }
 
     ret
@@ -76,30 +283,100 @@ pub fn make_td_results(
 
 /// Analagous to [make_td_results] but without all of the bookkeeping mapping
 /// individual molecules back to their corresponding TorsionDrives. Just pass in
-/// a Vec<Molecule> and get back a Vec<(id, cmiles, Vec<Geometry>)>. The
-/// Vec<Geometry> will always have length one. A vector is used just to keep the
-/// return type consistent with the TorsionDrive version.
+/// a Vec<Molecule> and get back a Vec<[ResultEntry]>. The conformers will
+/// always have length one. A vector is used just to keep the return type
+/// consistent with the TorsionDrive version.
 pub fn make_opt_results(
     results: Vec<TorsionDriveResult>,
     records: Vec<OptimizationRecord>,
     molecule_ids: HashMap<String, String>,
     molecules: HashMap<String, Molecule>,
-) -> Vec<(OptimizationRecord, String, Vec<Vec<f64>>)> {
-    // there may be more results than records, but accessing them with this map
-    // by the id stored on the records ensures that I only get the ones I want
-    let cmiles_map: HashMap<_, _> = results
-        .iter()
-        .map(|rec| (rec.record_id(), rec.cmiles()))
-        .collect();
+) -> Vec<ResultEntry<OptimizationRecord>> {
+    let cmiles_map = cmiles_map(&results);
 
     let mut ret = Vec::new();
     for record in records {
-        // do this first so we don't have to clone record.id
-        let id = &molecule_ids[&record.id];
         // sad clones
-        let geom = molecules[id].clone();
-        let cmiles = cmiles_map[&record.id].clone();
-        ret.push((record, cmiles, vec![geom.geometry]));
+        let Some(mol) = molecule_ids
+            .get(&record.id)
+            .and_then(|id| molecules.get(id))
+        else {
+            warn!("skipping record {}: missing final molecule", record.id);
+            continue;
+        };
+        let mol = mol.clone();
+        let Some(result) = collection_entry(&cmiles_map, &record.id) else {
+            continue;
+        };
+        ret.push(ResultEntry {
+            cmiles: result.cmiles().clone(),
+            inchi_key: result.fixed_hydrogen_inchi_key().clone(),
+            dataset: result.dataset.clone(),
+            record,
+            molecules: vec![mol],
+            initial_molecules: Vec::new(),
+        });
+    }
+
+    ret
+}
+
+/// the key for the cmiles in the extras of molecules submitted by qcsubmit
+const CMILES_KEY: &str = "canonical_isomeric_explicit_hydrogen_mapped_smiles";
+
+/// Analogous to [make_opt_results] for single-point [ResultRecord]s, where the
+/// conformer is the molecule the calculation was run on. Records missing from
+/// `results`, as happens when they were requested through a dataset's
+/// molecules, take their cmiles from the extras of their molecule instead
+pub fn make_sp_results(
+    results: Vec<TorsionDriveResult>,
+    records: Vec<ResultRecord>,
+    molecules: HashMap<String, Molecule>,
+) -> Vec<ResultEntry<ResultRecord>> {
+    let cmiles_map = cmiles_map(&results);
+
+    let mut ret = Vec::new();
+    for mut record in records {
+        let Some(mol) = molecules.get(&record.molecule).cloned() else {
+            warn!(
+                "missing molecule {} for record {}",
+                record.molecule, record.id
+            );
+            continue;
+        };
+        let (cmiles, inchi_key, dataset) = match cmiles_map.get(&record.id) {
+            Some(result) => (
+                result.cmiles().clone(),
+                result.fixed_hydrogen_inchi_key().clone(),
+                result.dataset.clone(),
+            ),
+            None => {
+                let extra = |k: &str| {
+                    mol.extras
+                        .as_ref()
+                        .and_then(|e| e.get(k)?.as_str())
+                        .unwrap_or_default()
+                        .to_owned()
+                };
+                let inchi_key = mol
+                    .identifiers
+                    .other
+                    .get("inchikey")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_owned();
+                (extra(CMILES_KEY), inchi_key, None)
+            }
+        };
+        record.reshape();
+        ret.push(ResultEntry {
+            record,
+            cmiles,
+            inchi_key,
+            molecules: vec![mol],
+            initial_molecules: Vec::new(),
+            dataset,
+        });
     }
 
     ret
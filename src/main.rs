@@ -16,18 +16,60 @@
 //! the very last line. The best we can do is return the building blocks of
 //! Molecules and their conformers, as the docs for [make_results] describe.
 
-use clap::{Parser, Subcommand};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use clap::{ArgAction, Args, Parser, Subcommand};
 use garage_door::{
     client::FractalClient,
-    collection::{CollectionGetBody, CollectionGetResponse, CollectionType},
+    collection::{
+        group_by_server, CollectionGetBody, CollectionGetResponse,
+        CollectionType, ResultCollection,
+    },
+    dedup::{self, DedupKey, DedupPolicy},
+    filter::{
+        self, ChargeFilter, ChargeRange, ConnectivityFilter, DihedralFilter,
+        ElementFilter, MultiplicityFilter, SpecFilter,
+    },
+    mapping,
+    metrics::Metrics,
+    procedure::{Driver, Record},
+    progress::{Bars, StderrWriter},
+    report::{self, ErrorEntry},
+    units::{EnergyUnit, LengthUnit},
+    validate::{self, check_dihedrals},
+    Metadata, Output, ResultEntry,
 };
-use openff_toolkit::qcsubmit::results::TorsionDriveResultCollection;
+use serde::Serialize;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Log more detail to stderr: `-v` for each request and `-vv` for
+    /// everything. `RUST_LOG` overrides this if it's set
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Save the raw bodies of any responses that fail or can't be decoded to
+    /// files in this directory
+    #[arg(long, global = true)]
+    dump_dir: Option<PathBuf>,
+
+    /// Write per-endpoint request counts, latencies, and sizes, along with
+    /// the time spent deserializing responses and assembling the output, to
+    /// this file as JSON, or to stderr if it's `-`
+    #[arg(long, global = true)]
+    metrics: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -39,110 +81,695 @@ enum Commands {
         #[arg(short, long)]
         dataset_type: CollectionType,
 
-        /// Data set name to retrieve
-        name: String,
+        /// Data set names to retrieve. Records appearing in more than one of
+        /// the data sets are only retrieved once, and the results from all of
+        /// the data sets are combined in the output
+        #[arg(required = true)]
+        names: Vec<String>,
+
+        /// Fetch the errors of any failed records in the data set, classify
+        /// them, and write the results to this file as JSON
+        #[arg(short, long)]
+        error_report: Option<String>,
+
+        /// Write the retrieved records to this file as a qcsubmit
+        /// ResultCollection, suitable for passing to `convert` later
+        #[arg(short, long)]
+        save_collection: Option<String>,
+
+        #[command(flatten)]
+        filters: FilterArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Convert an existing data set to a series of records and molecules
     Convert {
-        /// The type of dataset to be retrieved
+        /// The type of dataset to be retrieved. This is read from the `type`
+        /// field of the file, so it only needs to be passed as a check
         #[arg(short, long)]
-        dataset_type: CollectionType,
+        dataset_type: Option<CollectionType>,
 
         /// JSON file containing the data set
         filename: String,
+
+        /// Fetch the errors of any failed records in the data set, classify
+        /// them, and write the results to this file as JSON
+        #[arg(short, long)]
+        error_report: Option<String>,
+
+        #[command(flatten)]
+        filters: FilterArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Check that the conformers of each torsion drive in the output of `get`
+    /// or `convert` are at their grid angles, printing any that aren't as
+    /// JSON. Exits with a non-zero status if any are found
+    Validate {
+        /// JSON file containing torsion drive output
+        filename: String,
+
+        /// The allowed deviation from the grid angles in degrees
+        #[arg(short, long, default_value_t = 1.0)]
+        tolerance: f64,
     },
 }
 
+/// Options controlling the format of the output
+#[derive(Args)]
+struct OutputArgs {
+    /// The unit for the geometries in the output: bohr, angstrom, or nm
+    #[arg(long, default_value = "bohr")]
+    length_unit: LengthUnit,
+
+    /// The unit for the energies in the output: hartree, kcal/mol, or kJ/mol
+    #[arg(long, default_value = "hartree")]
+    energy_unit: EnergyUnit,
+
+    /// Include the full QCSchema molecules in the output under `molecules`
+    /// instead of just their geometries under `conformers`
+    #[arg(long)]
+    full_molecules: bool,
+
+    /// For single-point records, include these properties in the output
+    /// under `selected_properties`, given as a comma-separated list. The
+    /// names `dipole`, `quadrupole`, `mbis_charges`, and `mulliken_charges`
+    /// are recognized, and any other name is looked up in the record's
+    /// properties and qcvars
+    #[arg(long, value_delimiter = ',')]
+    properties: Option<Vec<String>>,
+
+    /// For single-point records whose `wavefunction` protocol kept one,
+    /// request the full wavefunction and include it in the output
+    #[arg(long)]
+    wavefunction: bool,
+
+    /// For torsion drives, request every optimization at each grid point,
+    /// not just the lowest-energy one, and include their final energies and
+    /// geometries in the output under `all_optimizations`, each marked as
+    /// the minimum or not
+    #[arg(long)]
+    all_optimizations: bool,
+
+    /// Request the initial molecules of each record and include their
+    /// geometries in the output under `initial_conformers`, or the full
+    /// molecules under `initial_molecules` with `--full-molecules`
+    #[arg(long)]
+    include_initial: bool,
+}
+
+impl OutputArgs {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            length_unit: self.length_unit,
+            energy_unit: self.energy_unit,
+        }
+    }
+}
+
+/// Filters to apply to the results before printing them
+#[derive(Args)]
+struct FilterArgs {
+    /// Only keep records computed with this QC method, like `b3lyp-d3bj`
+    #[arg(long)]
+    method: Option<String>,
+
+    /// Only keep records computed with this basis set, like `dzvp`. Pass
+    /// `none` to select methods without a basis set
+    #[arg(long)]
+    basis: Option<String>,
+
+    /// Only keep records computed with this QC program, like `psi4`
+    #[arg(long)]
+    program: Option<String>,
+
+    /// Only keep records computed with this driver: energy, gradient,
+    /// hessian, or properties. Mostly useful for single-point datasets
+    #[arg(long)]
+    driver: Option<Driver>,
+
+    /// Only keep entries whose molecules contain just these elements, given
+    /// as a comma-separated list of symbols like `C,H,N,O,S`
+    #[arg(long, value_delimiter = ',')]
+    allowed_elements: Option<Vec<String>>,
+
+    /// Remove entries whose bonds, guessed from their initial and final
+    /// geometries, differ from the bonds in their cmiles
+    #[arg(long)]
+    connectivity: bool,
+
+    /// The tolerance for guessing bonds with --connectivity, as a multiple of
+    /// the sum of the covalent radii of each pair of atoms
+    #[arg(long, default_value_t = 1.2, requires = "connectivity")]
+    connectivity_tolerance: f64,
+
+    /// Remove the conformers of torsion drives whose driven dihedral is not at
+    /// its grid angle
+    #[arg(long)]
+    check_dihedrals: bool,
+
+    /// The allowed deviation from the grid angles in degrees with
+    /// --check-dihedrals
+    #[arg(long, default_value_t = 1.0, requires = "check_dihedrals")]
+    dihedral_tolerance: f64,
+
+    /// Only keep entries whose molecules have this total charge, or a charge
+    /// in an inclusive range like `-1..1`
+    #[arg(long, allow_hyphen_values = true)]
+    charge: Option<ChargeRange>,
+
+    /// Only keep entries whose molecules have one of these multiplicities,
+    /// given as a comma-separated list
+    #[arg(long, value_delimiter = ',')]
+    multiplicity: Option<Vec<usize>>,
+
+    /// Collapse duplicate entries, either those sharing an InChI key
+    /// (`inchi`) or those sharing a cmiles and, for torsion drives, driven
+    /// dihedrals (`cmiles`)
+    #[arg(long, value_name = "KEY")]
+    deduplicate: Option<DedupKey>,
+
+    /// Which of a set of duplicates to keep with --deduplicate: the `first`
+    /// one retrieved or the one with the `lowest-energy`
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "first",
+        requires = "deduplicate"
+    )]
+    keep: DedupPolicy,
+}
+
+impl FilterArgs {
+    /// the filter on the `qc_spec` of the records, applied before requesting
+    /// their molecules
+    fn spec(&self) -> SpecFilter {
+        SpecFilter {
+            method: self.method.clone(),
+            basis: self.basis.clone(),
+            program: self.program.clone(),
+            driver: self.driver,
+        }
+    }
+
+    /// whether any of the filters need the initial molecules of the records
+    fn need_initial(&self) -> bool {
+        self.connectivity
+    }
+
+    /// remove the entries in `records` rejected by any of the filters
+    fn apply<R: Record>(&self, records: &mut Vec<ResultEntry<R>>) {
+        if let Some(elements) = &self.allowed_elements {
+            filter::apply(records, &ElementFilter::new(elements));
+        }
+        if self.connectivity {
+            filter::apply(
+                records,
+                &ConnectivityFilter::new(self.connectivity_tolerance),
+            );
+        }
+        if self.check_dihedrals {
+            DihedralFilter::new(self.dihedral_tolerance).apply(records);
+        }
+        if let Some(range) = self.charge {
+            filter::apply(records, &ChargeFilter::new(range));
+        }
+        if let Some(allowed) = &self.multiplicity {
+            filter::apply(records, &MultiplicityFilter::new(allowed.clone()));
+        }
+        // deduplicate last so that a rejected entry can't win over a good one
+        if let Some(key) = self.deduplicate {
+            dedup::deduplicate(records, key, self.keep);
+        }
+    }
+}
+
+/// log to `writer` at the level set by `RUST_LOG` if it's set, otherwise at a
+/// level set by the number of `-v` flags
+fn init_logging(verbose: u8, writer: StderrWriter) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(match verbose {
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        })
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(move || writer.clone())
+        .init();
+}
+
+/// exit with an error if any of the options in `filters` or `output` were
+/// passed but don't apply to `dataset_type`, instead of silently ignoring them
+fn check_applicable(
+    dataset_type: CollectionType,
+    filters: &FilterArgs,
+    output: &OutputArgs,
+) {
+    use CollectionType::*;
+    let td = dataset_type == TorsionDrive;
+    let sp = dataset_type == SinglePoint;
+    // reactions aren't records, so only the spec filters and energy unit
+    // apply to them
+    let records = dataset_type != Reaction;
+    let checks = [
+        ("--properties", output.properties.is_some(), sp),
+        ("--wavefunction", output.wavefunction, sp),
+        ("--all-optimizations", output.all_optimizations, td),
+        ("--check-dihedrals", filters.check_dihedrals, td),
+        (
+            "--length-unit",
+            output.length_unit != LengthUnit::Bohr,
+            records,
+        ),
+        ("--full-molecules", output.full_molecules, records),
+        ("--include-initial", output.include_initial, records),
+        (
+            "--allowed-elements",
+            filters.allowed_elements.is_some(),
+            records,
+        ),
+        ("--connectivity", filters.connectivity, records),
+        ("--charge", filters.charge.is_some(), records),
+        ("--multiplicity", filters.multiplicity.is_some(), records),
+        ("--deduplicate", filters.deduplicate.is_some(), records),
+    ];
+    for (flag, passed, applies) in checks {
+        if passed && !applies {
+            die(format!(
+                "{flag} is not supported for {dataset_type:?} datasets"
+            ));
+        }
+    }
+}
+
+/// print `msg` to stderr and exit with a non-zero status
+fn die(msg: impl Display) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1);
+}
+
+/// serialize `records` to JSON and print them to stdout, falling back on
+/// their Debug representation if that fails
+fn print_records<T: Debug + Serialize>(records: &T) {
+    match serde_json::to_string_pretty(records) {
+        Ok(s) => println!("{s}"),
+        Err(e) => {
+            error!(
+                "error serializing result to JSON: {e}. dumping what we can"
+            );
+            println!("{:#?}", records);
+        }
+    }
+}
+
+/// write the classified errors in `entries` to `filename` and a summary of
+/// them to stderr
+fn write_error_report(entries: &[ErrorEntry], filename: &str) {
+    for (category, count) in report::summarize(entries) {
+        info!("{category:?}: {count}");
+    }
+    write_json(&entries, filename);
+}
+
+/// write `value` to `filename` as JSON, reporting but otherwise ignoring any
+/// errors
+fn write_json<T: Serialize>(value: &T, filename: &str) {
+    let s = serde_json::to_string_pretty(value).unwrap();
+    std::fs::write(filename, s).unwrap_or_else(|e| {
+        error!("failed to write {filename}: {e}");
+    });
+}
+
+/// request each of the per-server collections in `groups` with its own
+/// [FractalClient] using `fetch` and combine the results. if `errors` is
+/// provided, the errors of any failed records in the `dataset_type`
+/// collections matching `spec` are also collected into it. if `initial` is
+/// true, the initial molecules of each entry are also requested. the clients
+/// share the settings of `base`
+async fn fetch_by_server<R, F, Fut>(
+    groups: Vec<(String, CollectionGetResponse)>,
+    base: &FractalClient,
+    dataset_type: CollectionType,
+    spec: &SpecFilter,
+    mut errors: Option<&mut Vec<ErrorEntry>>,
+    initial: bool,
+    fetch: F,
+) -> Vec<ResultEntry<R>>
+where
+    R: Record,
+    F: Fn(FractalClient, CollectionGetResponse, usize) -> Fut,
+    Fut: Future<Output = Vec<ResultEntry<R>>>,
+{
+    let mut ret = Vec::new();
+    for (address, col) in groups {
+        info!("requesting {} records from {address}", col.ids().len());
+        let client = base.for_address(address);
+        let query_limit = client.get_query_limit().await;
+        if let Some(errors) = errors.as_mut() {
+            errors.extend(
+                client
+                    .collection_error_report(
+                        &col,
+                        dataset_type,
+                        spec,
+                        query_limit,
+                    )
+                    .await,
+            );
+        }
+        let mut records = fetch(client.clone(), col, query_limit).await;
+        if initial {
+            client.initial_molecules(&mut records, query_limit).await;
+        }
+        ret.extend(records);
+    }
+    ret
+}
+
+/// put the molecules in `records` into their cmiles order, apply `filters`,
+/// convert them to the units in `output`, and print them to stdout. returns
+/// the `(record_id, cmiles, inchi_key)` of the records remaining after
+/// filtering
+fn finish<R>(
+    mut records: Vec<ResultEntry<R>>,
+    filters: &FilterArgs,
+    output: &OutputArgs,
+) -> Vec<(String, String, String)>
+where
+    R: Debug + Record + Serialize,
+{
+    // the filters comparing geometries to the cmiles rely on this too
+    mapping::reorder_entries(&mut records);
+    filters.apply(&mut records);
+    for r in &mut records {
+        r.convert_units(output.length_unit, output.energy_unit);
+        // the filters may have requested these without the user asking
+        if !output.include_initial {
+            r.initial_molecules.clear();
+        }
+    }
+    let ids = records
+        .iter()
+        .map(|r| {
+            let id = r.record.id().to_owned();
+            (id, r.cmiles.clone(), r.inchi_key.clone())
+        })
+        .collect();
+    if output.full_molecules {
+        print_records(&Output {
+            metadata: output.metadata(),
+            results: records.iter().map(ResultEntry::full).collect(),
+        });
+    } else {
+        print_records(&Output {
+            metadata: output.metadata(),
+            results: records,
+        });
+    }
+    ids
+}
+
+/// request the records of `dataset_type` in `groups`, filter them, and print
+/// them to stdout, writing an error report to `error_report` if requested.
+/// returns the `(record_id, cmiles, inchi_key)` of the records in the output
+async fn run(
+    groups: Vec<(String, CollectionGetResponse)>,
+    dataset_type: CollectionType,
+    filters: &FilterArgs,
+    output: &OutputArgs,
+    error_report: Option<String>,
+    base: &FractalClient,
+) -> Vec<(String, String, String)> {
+    let mut errors = Vec::new();
+    let errs = error_report.is_some().then_some(&mut errors);
+    let initial = filters.need_initial() || output.include_initial;
+    let spec = &filters.spec();
+    let ids = match dataset_type {
+        CollectionType::TorsionDrive => {
+            let records = fetch_by_server(
+                groups,
+                base,
+                dataset_type,
+                spec,
+                errs,
+                initial,
+                |client, col, ql| async move {
+                    let mut records =
+                        client.torsion_drive_records(col, ql, spec).await;
+                    if output.all_optimizations {
+                        client.all_optimizations(&mut records, ql).await;
+                    }
+                    records
+                },
+            )
+            .await;
+            finish(records, filters, output)
+        }
+        CollectionType::Optimization => {
+            let records = fetch_by_server(
+                groups,
+                base,
+                dataset_type,
+                spec,
+                errs,
+                initial,
+                |client, col, ql| async move {
+                    client.optimization_records(col, ql, spec).await
+                },
+            )
+            .await;
+            finish(records, filters, output)
+        }
+        CollectionType::GridOptimization => {
+            let records = fetch_by_server(
+                groups,
+                base,
+                dataset_type,
+                spec,
+                errs,
+                initial,
+                |client, col, ql| async move {
+                    client.grid_optimization_records(col, ql, spec).await
+                },
+            )
+            .await;
+            finish(records, filters, output)
+        }
+        CollectionType::SinglePoint => {
+            let records = fetch_by_server(
+                groups,
+                base,
+                dataset_type,
+                spec,
+                errs,
+                initial,
+                |client, col, ql| async move {
+                    let mut records =
+                        client.single_point_records(col, ql, spec).await;
+                    if let Some(names) = &output.properties {
+                        for r in &mut records {
+                            r.record.select_properties(names);
+                        }
+                    }
+                    if output.wavefunction {
+                        client.wavefunctions(&mut records, ql).await;
+                    }
+                    records
+                },
+            )
+            .await;
+            finish(records, filters, output)
+        }
+        // reactions aren't records, so only the spec filters apply to them
+        // and there are no record ids to return
+        CollectionType::Reaction => {
+            let mut results = Vec::new();
+            for (address, col) in groups {
+                let client = base.for_address(address);
+                let query_limit = client.get_query_limit().await;
+                if error_report.is_some() {
+                    errors.extend(
+                        client
+                            .collection_error_report(
+                                &col,
+                                dataset_type,
+                                spec,
+                                query_limit,
+                            )
+                            .await,
+                    );
+                }
+                results.extend(
+                    client.reaction_records(col, query_limit, spec).await,
+                );
+            }
+            let factor = output.energy_unit.hartree_factor();
+            for r in &mut results {
+                r.scale_energies(factor);
+            }
+            print_records(&Output {
+                metadata: output.metadata(),
+                results,
+            });
+            Vec::new()
+        }
+    };
+    if let Some(filename) = error_report {
+        write_error_report(&errors, &filename);
+    }
+    ids
+}
+
 #[tokio::main]
 async fn main() {
     let args = Cli::parse();
-    let client = FractalClient::new();
+    // progress bars are only drawn when stderr is a terminal, and log lines
+    // have to go around them
+    let bars = Bars::stderr();
+    init_logging(
+        args.verbose,
+        bars.as_ref().map(|b| b.writer()).unwrap_or_default(),
+    );
+    let metrics = args.metrics.as_ref().map(|_| Arc::new(Metrics::default()));
+    let base = FractalClient::new()
+        .with_progress(bars.as_ref().map(Bars::callback))
+        .with_dump_dir(args.dump_dir)
+        .with_metrics(metrics.clone());
     let start = std::time::Instant::now();
     match args.command {
-        Commands::Get { name, dataset_type } => {
-            let col = CollectionGetBody::new(dataset_type, name);
-            let (query_limit, collection) = tokio::join! {
-                client.get_query_limit(),
-                client.get_collection(col),
-            };
-            match dataset_type {
-                CollectionType::TorsionDrive => {
-                    let records = client
-                        .torsion_drive_records(collection, query_limit)
-                        .await;
-                    let s = serde_json::to_string_pretty(&records);
-                    if s.is_err() {
-                        eprintln!(
-                            "error serializing result to JSON.\
-                            dumping what we can"
-                        );
-                        println!("{:#?}", records);
-                    }
-                    println!("{}", s.unwrap());
-                }
-                CollectionType::Optimization => {
-                    let records = client
-                        .optimization_records(collection, query_limit)
-                        .await;
-                    let s = serde_json::to_string_pretty(&records);
-                    if s.is_err() {
-                        eprintln!(
-                            "error serializing result to JSON.\
-                            dumping what we can"
-                        );
-                        println!("{:#?}", records);
-                    }
-                    println!("{}", s.unwrap());
-                }
-                CollectionType::SinglePoint => {
-                    dbg!(collection);
-                }
+        Commands::Get {
+            names,
+            dataset_type,
+            error_report,
+            save_collection,
+            filters,
+            output,
+        } => {
+            if dataset_type == CollectionType::Reaction
+                && save_collection.is_some()
+            {
+                die("--save-collection is not supported for Reaction datasets");
+            }
+            check_applicable(dataset_type, &filters, &output);
+            let client = base.clone();
+            let cols = names
+                .into_iter()
+                .map(|name| CollectionGetBody::new(dataset_type, name))
+                .collect();
+            let collection = client.get_collections(cols).await;
+            let address = client.address().to_owned();
+            let groups = vec![(address.clone(), collection)];
+            let records = run(
+                groups,
+                dataset_type,
+                &filters,
+                &output,
+                error_report,
+                &base,
+            )
+            .await;
+            // built from the output rather than the collection, since
+            // single-point datasets only list their molecules
+            if let Some(filename) = save_collection {
+                let saved = ResultCollection::from_records(
+                    dataset_type,
+                    &address,
+                    records,
+                );
+                write_json(&saved, &filename);
             }
         }
         Commands::Convert {
             filename,
             dataset_type,
+            error_report,
+            filters,
+            output,
         } => {
-            // as I found out, you can always parse from file as a td collection
             let ds =
-                TorsionDriveResultCollection::parse_file(filename).unwrap();
-            let col: CollectionGetResponse = ds.into();
-            let query_limit = client.get_query_limit().await;
-            match dataset_type {
-                CollectionType::TorsionDrive => {
-                    let records =
-                        client.torsion_drive_records(col, query_limit).await;
-                    let s = serde_json::to_string_pretty(&records);
-                    if s.is_err() {
-                        eprintln!(
-                            "error serializing result to JSON.\
-                            dumping what we can"
-                        );
-                        println!("{:#?}", records);
-                    }
-                    println!("{}", s.unwrap());
-                }
-                CollectionType::Optimization => {
-                    let records =
-                        client.optimization_records(col, query_limit).await;
-                    let s = serde_json::to_string_pretty(&records);
-                    if s.is_err() {
-                        eprintln!(
-                            "error serializing result to JSON.\
-                            dumping what we can"
-                        );
-                        println!("{:#?}", records);
-                    }
-                    println!("{}", s.unwrap());
-                }
-                CollectionType::SinglePoint => todo!(),
+                ResultCollection::parse_file(&filename).unwrap_or_else(|e| {
+                    die(format!("failed to read {filename}: {e}"))
+                });
+            let declared = ds.collection_type().unwrap_or_else(|e| die(e));
+            let dataset_type = match dataset_type {
+                Some(t) if t != declared => die(format!(
+                    "{filename} contains a {}, not a {}",
+                    declared.result_collection_type(),
+                    t.result_collection_type()
+                )),
+                _ => declared,
+            };
+            check_applicable(dataset_type, &filters, &output);
+            // each server's entries have to be requested from that server
+            let groups = group_by_server(ds);
+            run(groups, dataset_type, &filters, &output, error_report, &base)
+                .await;
+        }
+        Commands::Validate {
+            filename,
+            tolerance,
+        } => {
+            let entries =
+                validate::read_output(&filename).unwrap_or_else(|e| {
+                    die(format!("failed to read {filename}: {e}"))
+                });
+            let deviations: Vec<_> = entries
+                .iter()
+                .flat_map(|e| {
+                    check_dihedrals(&e.record, e.conformers(), tolerance)
+                })
+                .collect();
+            let conformers: usize =
+                entries.iter().map(|e| e.conformers().len()).sum();
+            info!(
+                "{} of {conformers} conformers in {} records deviate by more \
+                 than {tolerance}°",
+                deviations.len(),
+                entries.len()
+            );
+            print_records(&deviations);
+            if !deviations.is_empty() {
+                std::process::exit(1);
             }
         }
     }
 
-    eprintln!(
+    if let (Some(metrics), Some(filename)) = (metrics, args.metrics) {
+        let report = metrics.report();
+        if filename == "-" {
+            eprintln!("{}", serde_json::to_string_pretty(&report).unwrap());
+        } else {
+            write_json(&report, &filename);
+        }
+    }
+
+    info!(
         "execution time: {:.1} s",
         start.elapsed().as_millis() as f64 / 1000.0
     );
+
+    report_skipped(&base);
+}
+
+/// log the ids `client` had to give up on, grouped by stage, and exit with a
+/// non-zero status if there were any, since the output is incomplete
+fn report_skipped(client: &FractalClient) {
+    let skipped = client.skipped();
+    if skipped.is_empty() {
+        return;
+    }
+    let mut by_stage: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (stage, id) in skipped {
+        by_stage.entry(stage).or_default().push(id);
+    }
+    for (stage, ids) in &by_stage {
+        error!("skipped {} {stage} ids: {}", ids.len(), ids.join(", "));
+    }
+    die("some ids couldn't be retrieved, so the output is incomplete");
 }
@@ -0,0 +1,459 @@
+//! Putting the atoms of QCArchive molecules into the atom map order of their
+//! cmiles. This is normally the order they are stored in already, but nothing
+//! guarantees it, so the orders are checked and fixed up if necessary.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::Display,
+};
+
+use tracing::{info, warn};
+
+use crate::{molecule::Molecule, procedure::Record, smiles, ResultEntry};
+
+/// the tolerance for guessing bonds when a molecule has no connectivity of its
+/// own, the same as the default for the connectivity filter
+const BOND_TOLERANCE: f64 = 1.2;
+
+/// A failure to put a molecule into the order of its cmiles
+#[derive(Debug)]
+pub enum OrderError {
+    /// the elements of the molecule differ from those in the cmiles, so the
+    /// cmiles doesn't describe it
+    Mismatch(String),
+
+    /// the order couldn't be determined, for example because the cmiles
+    /// couldn't be parsed or the bonds couldn't be guessed. the molecule may
+    /// still be in the right order
+    Unknown(String),
+}
+
+impl From<String> for OrderError {
+    fn from(value: String) -> Self {
+        Self::Unknown(value)
+    }
+}
+
+impl Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderError::Mismatch(s) | OrderError::Unknown(s) => {
+                write!(f, "{s}")
+            }
+        }
+    }
+}
+
+/// the atoms and bonds of one side of the matching
+struct Graph {
+    symbols: Vec<String>,
+    neighbors: Vec<HashSet<usize>>,
+}
+
+impl Graph {
+    fn new(
+        symbols: Vec<String>,
+        bonds: impl IntoIterator<Item = (usize, usize)>,
+    ) -> Self {
+        let mut neighbors = vec![HashSet::new(); symbols.len()];
+        for (i, j) in bonds {
+            neighbors[i].insert(j);
+            neighbors[j].insert(i);
+        }
+        Self { symbols, neighbors }
+    }
+
+    fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// the atoms in breadth-first order, starting a new search from the first
+    /// unvisited atom for each disconnected component
+    fn bfs_order(&self) -> Vec<usize> {
+        let mut seen = vec![false; self.len()];
+        let mut ret = Vec::with_capacity(self.len());
+        for start in 0..self.len() {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut queue = VecDeque::from([start]);
+            while let Some(i) = queue.pop_front() {
+                ret.push(i);
+                let mut next: Vec<_> = self.neighbors[i]
+                    .iter()
+                    .filter(|&&j| !seen[j])
+                    .copied()
+                    .collect();
+                next.sort();
+                for j in next {
+                    seen[j] = true;
+                    queue.push_back(j);
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// backtracking search for an element- and bond-preserving mapping from the
+/// atoms of `want` to those of `got`. `assign[i]` is the atom in `got`
+/// assigned to atom `i` in `want`
+struct Matcher<'a> {
+    want: &'a Graph,
+    got: &'a Graph,
+    order: Vec<usize>,
+    assign: Vec<Option<usize>>,
+    used: Vec<bool>,
+}
+
+impl Matcher<'_> {
+    /// whether atom `g` in `got` can be assigned to atom `w` in `want`, given
+    /// the assignments so far
+    fn feasible(&self, w: usize, g: usize) -> bool {
+        if self.used[g]
+            || self.want.symbols[w] != self.got.symbols[g]
+            || self.want.neighbors[w].len() != self.got.neighbors[g].len()
+        {
+            return false;
+        }
+        // every assigned neighbor of w must map to a neighbor of g, and g
+        // can't have any other assigned neighbors
+        let mut assigned = 0;
+        for &n in &self.want.neighbors[w] {
+            if let Some(m) = self.assign[n] {
+                if !self.got.neighbors[g].contains(&m) {
+                    return false;
+                }
+                assigned += 1;
+            }
+        }
+        let got_assigned = self.got.neighbors[g]
+            .iter()
+            .filter(|&&m| self.used[m])
+            .count();
+        assigned == got_assigned
+    }
+
+    fn search(&mut self, depth: usize) -> bool {
+        let Some(&w) = self.order.get(depth) else {
+            return true;
+        };
+        // if w has an assigned neighbor, only neighbors of its image are
+        // candidates
+        let candidates: Vec<usize> =
+            match self.want.neighbors[w].iter().find_map(|&n| self.assign[n]) {
+                Some(m) => self.got.neighbors[m].iter().copied().collect(),
+                None => (0..self.got.len()).collect(),
+            };
+        for g in candidates {
+            if !self.feasible(w, g) {
+                continue;
+            }
+            self.assign[w] = Some(g);
+            self.used[g] = true;
+            if self.search(depth + 1) {
+                return true;
+            }
+            self.assign[w] = None;
+            self.used[g] = false;
+        }
+        false
+    }
+}
+
+/// return the order of the atoms in `molecule` that puts them into the atom
+/// map order of `cmiles`, so that `molecule.permute(&order)` gives the mapped
+/// order, or `None` if they're already in that order. matching elements
+/// aren't enough for that, since atoms of the same element may be swapped, so
+/// the bonds have to match too. returns [OrderError::Mismatch] if the elements
+/// in the two differ, and [OrderError::Unknown] if `cmiles` is not fully
+/// mapped or no mapping preserving the bonds exists
+pub fn cmiles_order(
+    cmiles: &str,
+    molecule: &Molecule,
+) -> Result<Option<Vec<usize>>, OrderError> {
+    let smiles = smiles::parse(cmiles)?;
+    let n = smiles.atoms.len();
+    if n != molecule.natoms() {
+        return Err(OrderError::Unknown(format!(
+            "cmiles has {n} atoms but molecule {} has {}",
+            molecule.id,
+            molecule.natoms()
+        )));
+    }
+
+    let mut symbols = vec![String::new(); n];
+    for (i, atom) in smiles.atoms.iter().enumerate() {
+        let m = atom
+            .map
+            .and_then(|m| m.checked_sub(1))
+            .filter(|&m| m < n)
+            .ok_or_else(|| format!("atom {i} has an invalid map number"))?;
+        symbols[m].clone_from(&atom.symbol);
+    }
+    if symbols.iter().any(String::is_empty) {
+        return Err("duplicate atom map numbers".to_owned().into());
+    }
+
+    let got_symbols: Vec<String> = molecule
+        .symbols
+        .iter()
+        .map(|s| smiles::title_case(&s.to_lowercase()))
+        .collect();
+    let in_order = got_symbols == symbols;
+    if !in_order {
+        let (mut a, mut b) = (symbols.clone(), got_symbols.clone());
+        a.sort();
+        b.sort();
+        if a != b {
+            return Err(OrderError::Mismatch(format!(
+                "elements of molecule {} differ from those in the cmiles",
+                molecule.id
+            )));
+        }
+    }
+
+    let want = Graph::new(symbols, smiles.mapped_bonds()?);
+    let bonds: Vec<(usize, usize)> = if molecule.connectivity.is_empty() {
+        molecule
+            .guess_connectivity(BOND_TOLERANCE)?
+            .into_iter()
+            .collect()
+    } else {
+        molecule
+            .connectivity
+            .iter()
+            .map(|&(i, j, _)| (i, j))
+            .collect()
+    };
+    let got = Graph::new(got_symbols, bonds);
+    if in_order && want.neighbors == got.neighbors {
+        return Ok(None);
+    }
+
+    let mut matcher = Matcher {
+        want: &want,
+        got: &got,
+        order: want.bfs_order(),
+        assign: vec![None; n],
+        used: vec![false; n],
+    };
+    if !matcher.search(0) {
+        // the geometry may be too distorted to guess its bonds, but with the
+        // elements in order there's no reason to doubt the stored order. the
+        // connectivity filter can reject the entry if it's really broken
+        if in_order {
+            return Ok(None);
+        }
+        return Err(OrderError::Unknown(format!(
+            "no mapping between the bonds of molecule {} and its cmiles",
+            molecule.id
+        )));
+    }
+    let order: Vec<usize> =
+        matcher.assign.into_iter().map(Option::unwrap).collect();
+    if order.iter().enumerate().all(|(i, &j)| i == j) {
+        return Ok(None);
+    }
+    Ok(Some(order))
+}
+
+impl<R: Record> ResultEntry<R> {
+    /// put the atoms of all of the molecules in `self`, and any per-atom data
+    /// in its record, into the atom map order of its cmiles. the order is
+    /// determined from the first molecule and then applied to the rest, which
+    /// must share its order. entries without a cmiles are left alone, as is
+    /// `self` if any of its molecules can't be reordered
+    pub fn reorder_to_cmiles(&mut self) -> Result<(), OrderError> {
+        let Some(first) = self.molecules.first() else {
+            return Ok(());
+        };
+        if self.cmiles.is_empty() {
+            return Ok(());
+        }
+        let Some(order) = cmiles_order(&self.cmiles, first)? else {
+            return Ok(());
+        };
+        let want = {
+            let mut m = first.clone();
+            m.permute(&order);
+            m.symbols
+        };
+        let molecules = self.molecules.iter().chain(&self.initial_molecules);
+        for m in molecules {
+            if order.iter().zip(&want).any(|(&i, s)| &m.symbols[i] != s) {
+                return Err(OrderError::Unknown(format!(
+                    "molecule {} has a different atom order from the \
+                     others in its entry",
+                    m.id
+                )));
+            }
+        }
+        for m in self.molecules.iter_mut().chain(&mut self.initial_molecules) {
+            m.permute(&order);
+        }
+        self.record.permute_atoms(&order);
+        Ok(())
+    }
+}
+
+/// put the molecules in each of `entries` into their cmiles order with
+/// [ResultEntry::reorder_to_cmiles]. entries whose elements don't match their
+/// cmiles are removed and reported, while those whose order can't be
+/// determined are kept in their stored order with a warning. returns the
+/// number of entries removed
+pub fn reorder_entries<R: Record>(entries: &mut Vec<ResultEntry<R>>) -> usize {
+    let before = entries.len();
+    entries.retain_mut(|e| match e.reorder_to_cmiles() {
+        Ok(()) => true,
+        Err(OrderError::Mismatch(err)) => {
+            warn!("removing `{}`: {err}", e.cmiles);
+            false
+        }
+        Err(OrderError::Unknown(err)) => {
+            warn!("keeping `{}` in its stored order: {err}", e.cmiles);
+            true
+        }
+    });
+    let removed = before - entries.len();
+    if removed > 0 {
+        info!("removed {removed} of {before} entries with bad atom orders");
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use crate::procedure::{OptimizationRecord, Response};
+
+    use super::*;
+
+    fn load() -> Vec<(Molecule, String)> {
+        let s = read_to_string("testfiles/molecules.json").unwrap();
+        let c: Response<Molecule> = serde_json::from_str(&s).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&s).unwrap();
+        let cmiles = v["data"].as_array().unwrap().iter().map(|m| {
+            m["extras"]["canonical_isomeric_explicit_hydrogen_mapped_smiles"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        });
+        c.into_iter().zip(cmiles).collect()
+    }
+
+    #[test]
+    fn already_ordered() {
+        for (mol, cmiles) in load() {
+            assert_eq!(cmiles_order(&cmiles, &mol).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn shuffled() {
+        let (want, cmiles) = load().swap_remove(0);
+        let n = want.natoms();
+        // reverse the atoms, then swap the first two to make sure it's not
+        // just undoing a reversal
+        let mut shuffle: Vec<_> = (0..n).rev().collect();
+        shuffle.swap(0, 1);
+        for connectivity in [want.connectivity.clone(), Vec::new()] {
+            let mut got = want.clone();
+            got.connectivity = connectivity;
+            got.permute(&shuffle);
+            let order = cmiles_order(&cmiles, &got).unwrap().unwrap();
+            got.permute(&order);
+            assert_eq!(got.symbols, want.symbols);
+            // equivalent hydrogens may be swapped, so compare the bonds
+            // rather than the geometries
+            let tol = BOND_TOLERANCE;
+            assert_eq!(
+                got.guess_connectivity(tol).unwrap(),
+                want.guess_connectivity(tol).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn swapped_carbons() {
+        let (want, cmiles) = load().swap_remove(0);
+        // the methyl and methylene carbons, which keep the elements in order
+        // when swapped
+        let (i, j) = (6, 7);
+        assert_eq!(want.symbols[i], "C");
+        assert_eq!(want.symbols[j], "C");
+        let mut shuffle: Vec<_> = (0..want.natoms()).collect();
+        shuffle.swap(i, j);
+        for connectivity in [want.connectivity.clone(), Vec::new()] {
+            let mut got = want.clone();
+            got.connectivity = connectivity;
+            got.permute(&shuffle);
+            assert_eq!(got.symbols, want.symbols);
+            let order = cmiles_order(&cmiles, &got).unwrap().unwrap();
+            got.permute(&order);
+            let tol = BOND_TOLERANCE;
+            assert_eq!(
+                got.guess_connectivity(tol).unwrap(),
+                want.guess_connectivity(tol).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn element_mismatch() {
+        let (mut mol, cmiles) = load().swap_remove(0);
+        let i = mol.symbols.iter().position(|s| s == "H").unwrap();
+        mol.symbols[i] = "F".to_owned();
+        assert!(matches!(
+            cmiles_order(&cmiles, &mol),
+            Err(OrderError::Mismatch(_))
+        ));
+    }
+
+    #[test]
+    fn keep_unknown_orders() {
+        let s = read_to_string("testfiles/opt_procedure.json").unwrap();
+        let record = || {
+            let c: Response<OptimizationRecord> =
+                serde_json::from_str(&s).unwrap();
+            c.data.into_iter().next().unwrap()
+        };
+        let entry = |(mol, cmiles): (Molecule, String)| ResultEntry {
+            record: record(),
+            cmiles,
+            inchi_key: String::new(),
+            molecules: vec![mol],
+            initial_molecules: Vec::new(),
+            dataset: None,
+        };
+        let mut mols = load();
+        let (good, cmiles) = mols.swap_remove(0);
+
+        // a cmiles the parser rejects and a molecule with an extra atom say
+        // nothing about whether the stored order is right
+        let mut extra = good.clone();
+        extra.symbols.push("H".to_owned());
+        extra.geometry.extend([0.0; 3]);
+        let mut bad = good.clone();
+        let i = bad.symbols.iter().position(|s| s == "H").unwrap();
+        bad.symbols[i] = "F".to_owned();
+        let mut entries: Vec<_> = [
+            (good.clone(), "not a smiles((".to_owned()),
+            (extra, cmiles.clone()),
+            (bad, cmiles.clone()),
+            (good.clone(), cmiles),
+        ]
+        .into_iter()
+        .map(entry)
+        .collect();
+
+        assert_eq!(reorder_entries(&mut entries), 1);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].molecules[0].symbols, good.symbols);
+        assert_eq!(entries[0].molecules[0].geometry, good.geometry);
+        assert_eq!(entries[1].molecules[0].natoms(), good.natoms() + 1);
+        assert_eq!(entries[2].molecules[0].geometry, good.geometry);
+    }
+}
@@ -0,0 +1,200 @@
+//! Timing and size measurements for the requests made by a [FractalClient],
+//! for seeing where the time goes in a large retrieval and tracking it across
+//! server versions.
+//!
+//! [FractalClient]: crate::client::FractalClient
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+/// the measurements of a single request
+struct Sample {
+    latency: Duration,
+    bytes: usize,
+    deserialize: Duration,
+
+    /// whether the request failed, for example by timing out or returning an
+    /// error status
+    failed: bool,
+}
+
+/// Measurements collected from any number of clients. This is shared between
+/// them behind an `Arc`, so it's safe to record into from multiple tasks
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<String, Vec<Sample>>>,
+    assembly: Mutex<Duration>,
+}
+
+/// Summary statistics for the requests to one endpoint. Times are in
+/// milliseconds
+#[derive(Debug, PartialEq, Serialize)]
+pub struct EndpointReport {
+    /// the number of requests, including failed ones
+    pub requests: usize,
+    pub failures: usize,
+
+    /// the total size of the response bodies
+    pub bytes: usize,
+    pub total_latency_ms: f64,
+    pub p50_latency_ms: f64,
+    pub p90_latency_ms: f64,
+    pub p99_latency_ms: f64,
+    pub max_latency_ms: f64,
+    pub deserialize_ms: f64,
+}
+
+/// The summary of a [Metrics], as written by `--metrics`
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub endpoints: BTreeMap<String, EndpointReport>,
+
+    /// the time spent combining records and molecules into output entries
+    pub assembly_ms: f64,
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// return the `p`th percentile of `sorted` using the nearest-rank method
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Metrics {
+    /// record a request to `endpoint` that took `latency` to receive
+    /// `bytes`, followed by `deserialize` to decode
+    pub fn record_request(
+        &self,
+        endpoint: &str,
+        latency: Duration,
+        bytes: usize,
+        deserialize: Duration,
+    ) {
+        self.requests
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_owned())
+            .or_default()
+            .push(Sample {
+                latency,
+                bytes,
+                deserialize,
+                failed: false,
+            });
+    }
+
+    /// record a failed request to `endpoint` that took `latency` before
+    /// failing, after receiving `bytes`
+    pub fn record_failure(
+        &self,
+        endpoint: &str,
+        latency: Duration,
+        bytes: usize,
+    ) {
+        self.requests
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_owned())
+            .or_default()
+            .push(Sample {
+                latency,
+                bytes,
+                deserialize: Duration::ZERO,
+                failed: true,
+            });
+    }
+
+    /// add `duration` to the time spent assembling output entries
+    pub fn record_assembly(&self, duration: Duration) {
+        *self.assembly.lock().unwrap() += duration;
+    }
+
+    /// summarize the measurements so far
+    pub fn report(&self) -> Report {
+        let requests = self.requests.lock().unwrap();
+        let endpoints = requests
+            .iter()
+            .map(|(endpoint, samples)| {
+                let mut latencies: Vec<_> =
+                    samples.iter().map(|s| s.latency).collect();
+                latencies.sort();
+                let report = EndpointReport {
+                    requests: samples.len(),
+                    failures: samples.iter().filter(|s| s.failed).count(),
+                    bytes: samples.iter().map(|s| s.bytes).sum(),
+                    total_latency_ms: ms(latencies.iter().sum()),
+                    p50_latency_ms: ms(percentile(&latencies, 50.0)),
+                    p90_latency_ms: ms(percentile(&latencies, 90.0)),
+                    p99_latency_ms: ms(percentile(&latencies, 99.0)),
+                    max_latency_ms: ms(percentile(&latencies, 100.0)),
+                    deserialize_ms: ms(samples
+                        .iter()
+                        .map(|s| s.deserialize)
+                        .sum()),
+                };
+                (endpoint.clone(), report)
+            })
+            .collect();
+        Report {
+            endpoints,
+            assembly_ms: ms(*self.assembly.lock().unwrap()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn report() {
+        let m = Metrics::default();
+        for i in 1..=10 {
+            m.record_request(
+                "molecule",
+                Duration::from_millis(i * 10),
+                100,
+                Duration::from_millis(1),
+            );
+        }
+        m.record_request(
+            "procedure",
+            Duration::from_millis(5),
+            7,
+            Duration::ZERO,
+        );
+        m.record_failure("procedure", Duration::from_millis(120), 0);
+        m.record_assembly(Duration::from_millis(3));
+        m.record_assembly(Duration::from_millis(4));
+
+        let r = m.report();
+        assert!(close(r.assembly_ms, 7.0));
+        let mol = &r.endpoints["molecule"];
+        assert_eq!(mol.requests, 10);
+        assert_eq!(mol.failures, 0);
+        assert_eq!(mol.bytes, 1000);
+        assert!(close(mol.total_latency_ms, 550.0));
+        assert!(close(mol.p50_latency_ms, 50.0));
+        assert!(close(mol.p90_latency_ms, 90.0));
+        assert!(close(mol.p99_latency_ms, 100.0));
+        assert!(close(mol.max_latency_ms, 100.0));
+        assert!(close(mol.deserialize_ms, 10.0));
+        let proc = &r.endpoints["procedure"];
+        assert_eq!(proc.requests, 2);
+        assert_eq!(proc.failures, 1);
+        assert_eq!(proc.bytes, 7);
+        assert!(close(proc.p50_latency_ms, 5.0));
+        assert!(close(proc.max_latency_ms, 120.0));
+    }
+}
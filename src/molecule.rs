@@ -1,5 +1,11 @@
-use crate::client::Body;
-use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::{client::Body, elements::covalent_radius};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// the conversion factor from bohr to Å used by qcelemental (CODATA 2014)
+pub const BOHR_TO_ANGSTROM: f64 = 0.52917721067;
 
 #[derive(Default, Serialize)]
 struct QueryFilter {
@@ -33,32 +39,223 @@ impl Body for MoleculeGetBody {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Identifiers {
     pub molecule_hash: String,
     pub molecular_formula: String,
+
+    /// any of the other, optional identifiers like `smiles` or `inchikey`
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// deserialize a `null` as the default value of `T`
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// A QCSchema molecule. The optional fields are omitted from the serialized
+/// output when the server didn't provide them, so serializing gives back
+/// something close to the original
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Molecule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validated: Option<bool>,
+
     pub symbols: Vec<String>,
     pub geometry: Vec<f64>,
     pub name: String,
     pub identifiers: Identifiers,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
     /// looks like an int in my test cases, but the
     /// qcelemental/models/molecule.py says float
     pub molecular_charge: f64,
 
     pub molecular_multiplicity: usize,
 
-    /// tuple of `(atom_index1, atom_index2, bond_order)`
+    // per-atom data, in the same order as `symbols`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub masses: Option<Vec<f64>>,
+
+    /// `false` for ghost atoms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub real: Option<Vec<bool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atom_labels: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atomic_numbers: Option<Vec<usize>>,
+
+    /// -1 for atoms without a known mass number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mass_numbers: Option<Vec<isize>>,
+
+    /// tuple of `(atom_index1, atom_index2, bond_order)`. empty if the server
+    /// didn't store any bonds
+    #[serde(default, deserialize_with = "null_as_default")]
     pub connectivity: Vec<(usize, usize, f64)>,
 
+    /// the atom indices in each fragment, for dimers and other multi-fragment
+    /// systems
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragments: Option<Vec<Vec<usize>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment_charges: Option<Vec<f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment_multiplicities: Option<Vec<usize>>,
+
     pub fix_com: bool,
     pub fix_orientation: bool,
-    pub fix_symmetry: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fix_symmetry: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Value>,
     pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<HashMap<String, Value>>,
+}
+
+impl Molecule {
+    /// return the number of atoms in the molecule
+    pub fn natoms(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// return the coordinates of atom `i`
+    pub fn coord(&self, i: usize) -> [f64; 3] {
+        [
+            self.geometry[3 * i],
+            self.geometry[3 * i + 1],
+            self.geometry[3 * i + 2],
+        ]
+    }
+
+    /// return the distance between atoms `i` and `j` in the units of the
+    /// geometry, normally bohr
+    pub fn distance(&self, i: usize, j: usize) -> f64 {
+        let (a, b) = (self.coord(i), self.coord(j));
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    /// reorder the atoms in the molecule so that the new atom `i` is the old
+    /// atom `order[i]`. `order` must be a permutation of the atom indices
+    pub fn permute(&mut self, order: &[usize]) {
+        assert_eq!(order.len(), self.natoms());
+        let mut inverse = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            inverse[old] = new;
+        }
+        fn reorder<T: Clone>(v: &mut Vec<T>, order: &[usize]) {
+            *v = order.iter().map(|&i| v[i].clone()).collect();
+        }
+        reorder(&mut self.symbols, order);
+        self.geometry = order.iter().flat_map(|&i| self.coord(i)).collect();
+        if let Some(v) = &mut self.masses {
+            reorder(v, order);
+        }
+        if let Some(v) = &mut self.real {
+            reorder(v, order);
+        }
+        if let Some(v) = &mut self.atom_labels {
+            reorder(v, order);
+        }
+        if let Some(v) = &mut self.atomic_numbers {
+            reorder(v, order);
+        }
+        if let Some(v) = &mut self.mass_numbers {
+            reorder(v, order);
+        }
+        for (i, j, _) in &mut self.connectivity {
+            (*i, *j) = (inverse[*i], inverse[*j]);
+        }
+        for fragment in self.fragments.iter_mut().flatten() {
+            for i in fragment.iter_mut() {
+                *i = inverse[*i];
+            }
+            fragment.sort();
+        }
+    }
+
+    /// guess the bonds in the molecule from its geometry, like qcelemental's
+    /// `guess_connectivity`. atoms `i` and `j` are considered bonded if the
+    /// distance between them is less than `tolerance` times the sum of their
+    /// covalent radii. the bonds are returned as pairs of atom indices with
+    /// the smaller index first. returns an error if any of the elements lack
+    /// a covalent radius. assumes the geometry is in bohr
+    pub fn guess_connectivity(
+        &self,
+        tolerance: f64,
+    ) -> Result<HashSet<(usize, usize)>, String> {
+        let radii = self
+            .symbols
+            .iter()
+            .map(|s| {
+                covalent_radius(s)
+                    .map(|r| r / BOHR_TO_ANGSTROM)
+                    .ok_or_else(|| format!("no covalent radius for {s}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut ret = HashSet::new();
+        for i in 0..self.natoms() {
+            for j in i + 1..self.natoms() {
+                if self.distance(i, j) < tolerance * (radii[i] + radii[j]) {
+                    ret.insert((i, j));
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// return the dihedral angle in degrees, in the range (-180, 180], between the
+/// atoms with indices `(i, j, k, l)` in the flattened `geometry`, as in
+/// [Molecule::geometry]
+pub fn dihedral(
+    geometry: &[f64],
+    (i, j, k, l): (usize, usize, usize, usize),
+) -> f64 {
+    let coord =
+        |i: usize| [geometry[3 * i], geometry[3 * i + 1], geometry[3 * i + 2]];
+    let sub =
+        |a: [f64; 3], b: [f64; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let cross = |a: [f64; 3], b: [f64; 3]| {
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    };
+    let dot =
+        |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+    let b1 = sub(coord(j), coord(i));
+    let b2 = sub(coord(k), coord(j));
+    let b3 = sub(coord(l), coord(k));
+    let b2_len = dot(b2, b2).sqrt();
+    let x = dot(cross(b1, b2), cross(b2, b3));
+    let y = b2_len * dot(b1, cross(b2, b3));
+    // atan2 returns values in [-180, 180], so fold -180 into 180
+    let ret = y.atan2(x).to_degrees();
+    if ret <= -180.0 {
+        ret + 360.0
+    } else {
+        ret
+    }
 }
 
 #[cfg(test)]
@@ -75,4 +272,95 @@ mod tests {
         let c: Response<Molecule> = serde_json::from_str(&s).unwrap();
         dbg!(c);
     }
+
+    #[test]
+    fn dihedrals() {
+        // four atoms in the xy-plane with the last rotated around the j-k
+        // (x) axis by `want`
+        for want in [0.0, 60.0, -60.0, 120.0, 180.0, -179.0] {
+            let t: f64 = f64::to_radians(want);
+            let geom = [
+                0.0,
+                1.0,
+                0.0, //
+                0.0,
+                0.0,
+                0.0, //
+                1.5,
+                0.0,
+                0.0, //
+                1.5,
+                t.cos(),
+                t.sin(),
+            ];
+            let got = dihedral(&geom, (0, 1, 2, 3));
+            assert!((got - want).abs() < 1e-10, "got {got}, want {want}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = read_to_string("testfiles/molecules.json").unwrap();
+        let want: serde_json::Value = serde_json::from_str(&s).unwrap();
+        let c: Response<Molecule> = serde_json::from_str(&s).unwrap();
+        let got = serde_json::to_value(&c.data).unwrap();
+        // integers like the charges come back as floats, so only compare the
+        // fields present, and then check that a second trip is lossless
+        let keys = |v: &serde_json::Value| {
+            let mut k: Vec<_> =
+                v.as_object().unwrap().keys().cloned().collect();
+            k.sort();
+            k
+        };
+        for (got, want) in got
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(want["data"].as_array().unwrap())
+        {
+            assert_eq!(keys(got), keys(want));
+            assert_eq!(got["extras"], want["extras"]);
+            assert_eq!(got["provenance"], want["provenance"]);
+            assert_eq!(got["geometry"], want["geometry"]);
+        }
+        let again: Vec<Molecule> = serde_json::from_value(got.clone()).unwrap();
+        assert_eq!(serde_json::to_value(again).unwrap(), got);
+    }
+
+    #[test]
+    fn permute_dimer() {
+        let mut mol: Molecule = serde_json::from_value(serde_json::json!({
+            "symbols": ["O", "H", "H", "Ne"],
+            "geometry": [
+                0.0, 0.0, 0.0, 1.8, 0.0, 0.0, 0.0, 1.8, 0.0, 6.0, 0.0, 0.0
+            ],
+            "name": "H2O-Ne",
+            "identifiers": {
+                "molecule_hash": "",
+                "molecular_formula": "H2NeO",
+            },
+            "molecular_charge": 0.0,
+            "molecular_multiplicity": 1,
+            "masses": [15.99, 1.01, 1.01, 19.99],
+            "real": [true, true, true, false],
+            "connectivity": null,
+            "fragments": [[0, 1, 2], [3]],
+            "fragment_charges": [0.0, 0.0],
+            "fragment_multiplicities": [1, 1],
+            "fix_com": false,
+            "fix_orientation": false,
+            "id": "1",
+        }))
+        .unwrap();
+        assert!(mol.connectivity.is_empty());
+        mol.permute(&[3, 1, 0, 2]);
+        assert_eq!(mol.symbols, ["Ne", "H", "O", "H"]);
+        assert_eq!(mol.coord(0), [6.0, 0.0, 0.0]);
+        assert_eq!(
+            mol.masses.as_deref(),
+            Some(&[19.99, 1.01, 15.99, 1.01][..])
+        );
+        assert_eq!(mol.real.as_deref(), Some(&[false, true, true, true][..]));
+        assert_eq!(mol.fragments, Some(vec![vec![1, 2, 3], vec![0]]));
+    }
 }
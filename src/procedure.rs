@@ -1,7 +1,7 @@
 //! [FractalClient] queries for procedures like [TorsionDrive]s and
 //! [Optimization]s.
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -32,6 +32,15 @@ pub struct ProcedureGetBody {
     data: Data,
 }
 
+impl ProcedureGetBody {
+    /// request records with `status` instead of the default
+    /// [Status::Complete]
+    pub fn with_status(mut self, status: Status) -> Self {
+        self.data.status = status;
+        self
+    }
+}
+
 impl Body for ProcedureGetBody {
     fn new(id: Vec<String>) -> Self {
         Self {
@@ -48,6 +57,99 @@ impl Body for ProcedureGetBody {
     }
 }
 
+/// Behavior shared by the different kinds of procedure records
+pub trait Record {
+    /// the id of the record
+    fn id(&self) -> &str;
+
+    /// the ids of the initial molecules of the record
+    fn initial_molecules(&self) -> Vec<String>;
+
+    /// the lowest final energy of the record, if it has any energies
+    fn energy(&self) -> Option<f64>;
+
+    /// the dihedrals driven by the record, if it is a torsion drive
+    fn dihedrals(&self) -> Option<&[(usize, usize, usize, usize)]> {
+        None
+    }
+
+    /// the specification of the quantum chemistry calculations underlying the
+    /// record
+    fn qc_spec(&self) -> &QCSpecification;
+
+    /// multiply all of the energies in the record by `factor`, for converting
+    /// them out of hartree
+    fn scale_energies(&mut self, factor: f64);
+
+    /// divide any derivatives in the record by the appropriate power of
+    /// `factor`, for converting them out of bohr. [Self::scale_energies]
+    /// handles their energy part
+    fn scale_lengths(&mut self, _factor: f64) {}
+
+    /// reorder any per-atom data or atom indices in the record to match
+    /// [Molecule::permute] with the same `order`
+    ///
+    /// [Molecule::permute]: crate::molecule::Molecule::permute
+    fn permute_atoms(&mut self, _order: &[usize]) {}
+
+    /// remove the grid point `grid_id` and its energies and optimizations
+    /// from the record, if it is a torsion drive
+    fn remove_grid_point(&mut self, _grid_id: &str) {}
+
+    /// the grid ids and the corresponding angles of each grid point, in the
+    /// same order as the conformers in a [ResultEntry], if the record is a
+    /// torsion drive
+    ///
+    /// [ResultEntry]: crate::ResultEntry
+    fn grid_points(&self) -> Option<Vec<(&str, Vec<f64>)>> {
+        None
+    }
+}
+
+/// The quantity computed by a single-point calculation
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    #[default]
+    Energy,
+    Gradient,
+    Hessian,
+    Properties,
+}
+
+impl FromStr for Driver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "energy" => Ok(Self::Energy),
+            "gradient" => Ok(Self::Gradient),
+            "hessian" => Ok(Self::Hessian),
+            "properties" => Ok(Self::Properties),
+            _ => Err(format!("unmatched Driver: `{s}`")),
+        }
+    }
+}
+
+/// The `qc_spec` of a procedure, describing how each gradient (or energy, etc.)
+/// was computed
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct QCSpecification {
+    pub driver: Driver,
+    pub method: String,
+
+    /// this is `None` for methods without a basis set, like xtb
+    pub basis: Option<String>,
+    pub program: String,
+
+    /// the id of a keyword set in the `keyword` table, not the keywords
+    /// themselves
+    pub keywords: Option<String>,
+
+    #[serde(default)]
+    pub protocols: HashMap<String, Value>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OptimizationSpec {
     pub program: String,
@@ -56,7 +158,7 @@ pub struct OptimizationSpec {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TDKeywords {
-    dihedrals: Vec<(usize, usize, usize, usize)>,
+    pub dihedrals: Vec<(usize, usize, usize, usize)>,
     grid_spacing: Vec<isize>,
     dihedral_ranges: Option<Vec<(isize, isize)>>,
     energy_decrease_thresh: Option<f64>,
@@ -87,7 +189,7 @@ pub struct TorsionDriveRecord {
     pub initial_molecule: Vec<String>,
     pub keywords: TDKeywords,
     pub optimization_spec: OptimizationSpec,
-    pub qc_spec: Value,
+    pub qc_spec: QCSpecification,
     // output data
     #[serde(rename = "final_energy_dict")]
     pub final_energies: HashMap<String, f64>,
@@ -118,9 +220,93 @@ pub struct TorsionDriveRecord {
     /// }
     /// ```
     pub minimum_positions: HashMap<String, usize>,
+
+    /// every optimization in `optimization_history`, sorted by grid id and
+    /// then by position in the history. this isn't part of the response but
+    /// is filled in by [FractalClient::all_optimizations] when requested
+    ///
+    /// [FractalClient::all_optimizations]: crate::client::FractalClient::all_optimizations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub all_optimizations: Vec<GridPointOptimization>,
+}
+
+/// One of the optimizations run at a grid point of a torsion drive
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GridPointOptimization {
+    pub grid_id: String,
+    pub optimization_id: String,
+
+    /// whether this is the lowest-energy optimization at its grid point, the
+    /// one whose final molecule is used for the conformer
+    pub minimum: bool,
+
+    /// the final energy of the optimization
+    pub energy: Option<f64>,
+
+    /// the geometry of the final molecule of the optimization
+    pub geometry: Vec<f64>,
+}
+
+/// return the inverse of the permutation `order`, mapping old atom indices to
+/// new ones
+fn inverse_permutation(order: &[usize]) -> Vec<usize> {
+    let mut ret = vec![0; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        ret[old] = new;
+    }
+    ret
+}
+
+/// the key for the optional preoptimization in the `grid_optimizations` and
+/// `final_energy_dict` of a [GridOptimizationRecord], which isn't a grid point
+const PREOPTIMIZATION: &str = "preoptimization";
+
+/// parse a grid id like `[-120]` or `[-120, 30]` into its angles
+fn parse_grid_id(grid_id: &str) -> Result<Vec<isize>, String> {
+    serde_json::from_str(grid_id)
+        .map_err(|e| format!("invalid grid id `{grid_id}`: {e}"))
+}
+
+/// sort `grid_ids` by their angles or step indices, leaving out the
+/// preoptimization. returns an error if any of the others can't be parsed
+fn sort_grid_ids<'a>(
+    grid_ids: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<&'a String>, String> {
+    let mut ret = grid_ids
+        .into_iter()
+        .filter(|g| *g != PREOPTIMIZATION)
+        .map(|g| Ok((parse_grid_id(g)?, g)))
+        .collect::<Result<Vec<_>, String>>()?;
+    ret.sort();
+    Ok(ret.into_iter().map(|(_, g)| g).collect())
 }
 
 impl TorsionDriveRecord {
+    /// return the (grid_id, optimization_id, minimum) triples for every
+    /// optimization in `optimization_history`, sorted by grid id and then by
+    /// position in the history. returns an error if any of the grid ids are
+    /// invalid
+    pub fn optimization_history(
+        &self,
+    ) -> Result<Vec<(&String, &String, bool)>, String> {
+        let mut ret = Vec::new();
+        for grid_id in self.sorted_grid_ids()? {
+            let min = self.minimum_positions[grid_id];
+            for (i, opt_id) in
+                self.optimization_history[grid_id].iter().enumerate()
+            {
+                ret.push((grid_id, opt_id, i == min));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// return the grid ids of the record sorted by their angles, or an error
+    /// if any of them are invalid
+    pub fn sorted_grid_ids(&self) -> Result<Vec<&String>, String> {
+        sort_grid_ids(self.minimum_positions.keys())
+    }
+
     /// return an iterator over the optimization_id -> (record_id, grid_id)
     /// pairs in self.optimization_history. the keys are the ids of the
     /// OptimizationRecords associated with each point along the torsion drive
@@ -136,6 +322,81 @@ impl TorsionDriveRecord {
     }
 }
 
+impl Record for TorsionDriveRecord {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn initial_molecules(&self) -> Vec<String> {
+        self.initial_molecule.clone()
+    }
+
+    fn energy(&self) -> Option<f64> {
+        self.final_energies.values().copied().reduce(f64::min)
+    }
+
+    fn dihedrals(&self) -> Option<&[(usize, usize, usize, usize)]> {
+        Some(&self.keywords.dihedrals)
+    }
+
+    fn qc_spec(&self) -> &QCSpecification {
+        &self.qc_spec
+    }
+
+    fn scale_energies(&mut self, factor: f64) {
+        self.final_energies.values_mut().for_each(|e| *e *= factor);
+        for opt in &mut self.all_optimizations {
+            if let Some(e) = &mut opt.energy {
+                *e *= factor;
+            }
+        }
+    }
+
+    fn scale_lengths(&mut self, factor: f64) {
+        for opt in &mut self.all_optimizations {
+            opt.geometry.iter_mut().for_each(|x| *x *= factor);
+        }
+    }
+
+    fn permute_atoms(&mut self, order: &[usize]) {
+        let inverse = inverse_permutation(order);
+        for (i, j, k, l) in &mut self.keywords.dihedrals {
+            (*i, *j, *k, *l) =
+                (inverse[*i], inverse[*j], inverse[*k], inverse[*l]);
+        }
+        for opt in &mut self.all_optimizations {
+            if opt.geometry.len() == 3 * order.len() {
+                opt.geometry = order
+                    .iter()
+                    .flat_map(|&i| opt.geometry[3 * i..3 * i + 3].to_vec())
+                    .collect();
+            }
+        }
+    }
+
+    fn remove_grid_point(&mut self, grid_id: &str) {
+        self.final_energies.remove(grid_id);
+        self.optimization_history.remove(grid_id);
+        self.minimum_positions.remove(grid_id);
+        self.all_optimizations.retain(|o| o.grid_id != grid_id);
+    }
+
+    /// records with invalid grid ids are skipped when they're retrieved, so
+    /// this only returns `None` for records that couldn't have been output
+    fn grid_points(&self) -> Option<Vec<(&str, Vec<f64>)>> {
+        let ret = self
+            .sorted_grid_ids()
+            .ok()?
+            .into_iter()
+            .map(|g| {
+                let angles = parse_grid_id(g).ok()?.into_iter();
+                Some((g.as_str(), angles.map(|a| a as f64).collect()))
+            })
+            .collect::<Option<_>>()?;
+        Some(ret)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OptimizationRecord {
     // base identification
@@ -156,7 +417,7 @@ pub struct OptimizationRecord {
     pub provenance: Option<Value>,
     // input data
     pub initial_molecule: String,
-    pub qc_spec: Value,
+    pub qc_spec: QCSpecification,
     pub keywords: HashMap<String, Value>,
     // results
     pub energies: Vec<f64>,
@@ -164,9 +425,199 @@ pub struct OptimizationRecord {
     pub trajectory: Vec<String>,
 }
 
+impl Record for OptimizationRecord {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn initial_molecules(&self) -> Vec<String> {
+        vec![self.initial_molecule.clone()]
+    }
+
+    fn energy(&self) -> Option<f64> {
+        self.energies.last().copied()
+    }
+
+    fn qc_spec(&self) -> &QCSpecification {
+        &self.qc_spec
+    }
+
+    fn scale_energies(&mut self, factor: f64) {
+        self.energies.iter_mut().for_each(|e| *e *= factor);
+    }
+}
+
+/// One of the coordinates scanned in a [GridOptimizationRecord]
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScanDimension {
+    /// `distance`, `angle`, or `dihedral`
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub indices: Vec<usize>,
+    pub steps: Vec<f64>,
+
+    /// `absolute` or `relative` to the starting geometry
+    pub step_type: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GOKeywords {
+    pub scans: Vec<ScanDimension>,
+    #[serde(default)]
+    pub preoptimization: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GridOptimizationRecord {
+    // base identification
+    pub id: String,
+    pub hash_index: Option<String>,
+    pub procedure: String,
+    pub program: String,
+    pub version: usize,
+    pub protocols: Option<HashMap<String, Value>>,
+    pub extras: HashMap<String, Value>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub error: Option<String>,
+    pub manager_name: Option<String>,
+    pub status: Status,
+    pub modified_on: String,
+    pub created_on: String,
+    pub provenance: Option<Value>,
+    // input data
+    pub initial_molecule: String,
+
+    /// the initial molecule after the optional preoptimization
+    pub starting_molecule: Option<String>,
+    pub keywords: GOKeywords,
+    pub optimization_spec: OptimizationSpec,
+    pub qc_spec: QCSpecification,
+    // output data
+    /// the grid point of the starting molecule, as step indices into each of
+    /// the scans
+    pub starting_grid: Option<Vec<isize>>,
+    #[serde(rename = "final_energy_dict")]
+    pub final_energies: HashMap<String, f64>,
+
+    /// A map of grid points, as step indices like `[0, 1]`, to the id of the
+    /// OptimizationRecord at that point
+    pub grid_optimizations: HashMap<String, String>,
+}
+
+impl GridOptimizationRecord {
+    /// return the grid ids of the record sorted by their step indices,
+    /// leaving out the preoptimization, or an error if any of them are invalid
+    pub fn sorted_grid_ids(&self) -> Result<Vec<&String>, String> {
+        sort_grid_ids(self.grid_optimizations.keys())
+    }
+
+    /// return an iterator over the optimization_id -> (record_id, grid_id)
+    /// pairs in self.grid_optimizations, like
+    /// [TorsionDriveRecord::optimizations]. the preoptimization is left out
+    pub(crate) fn optimizations(
+        &self,
+    ) -> impl Iterator<Item = (String, (String, String))> + '_ {
+        self.grid_optimizations
+            .iter()
+            .filter(|(grid_id, _)| *grid_id != PREOPTIMIZATION)
+            .map(|(grid_id, opt_id)| {
+                (opt_id.clone(), (self.id.clone(), grid_id.clone()))
+            })
+    }
+}
+
+impl Record for GridOptimizationRecord {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn initial_molecules(&self) -> Vec<String> {
+        vec![self.initial_molecule.clone()]
+    }
+
+    fn energy(&self) -> Option<f64> {
+        self.final_energies
+            .iter()
+            .filter(|(grid_id, _)| *grid_id != PREOPTIMIZATION)
+            .map(|(_, e)| *e)
+            .reduce(f64::min)
+    }
+
+    fn qc_spec(&self) -> &QCSpecification {
+        &self.qc_spec
+    }
+
+    fn scale_energies(&mut self, factor: f64) {
+        self.final_energies.values_mut().for_each(|e| *e *= factor);
+    }
+
+    fn permute_atoms(&mut self, order: &[usize]) {
+        let inverse = inverse_permutation(order);
+        for scan in &mut self.keywords.scans {
+            scan.indices.iter_mut().for_each(|i| *i = inverse[*i]);
+        }
+    }
+}
+
+/// the fields common to every kind of procedure record that are needed to
+/// figure out why it failed
+#[derive(Debug, Deserialize)]
+pub struct FailedRecord {
+    pub id: String,
+    pub procedure: String,
+    pub status: Status,
+    pub error: Option<String>,
+    pub stdout: Option<String>,
+    pub manager_name: Option<String>,
+}
+
+/// The `meta` field of a server response, describing how much of the request
+/// the server was able to fulfil
+#[derive(Debug, Deserialize)]
+pub struct Meta {
+    #[serde(default = "yes")]
+    pub success: bool,
+
+    /// an explanation of the failure when `success` is false
+    #[serde(default)]
+    pub error_description: Value,
+
+    /// errors the server encountered while handling the request
+    #[serde(default)]
+    pub errors: Vec<Value>,
+
+    /// the requested ids that the server couldn't find
+    #[serde(default, deserialize_with = "id_strings")]
+    pub missing: Vec<String>,
+
+    /// the number of matching entries. this can be more than the number
+    /// returned if the response was truncated
+    #[serde(default)]
+    pub n_found: usize,
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// deserialize a list of ids given as either strings or numbers
+fn id_strings<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Vec::<Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|v| match v {
+            Value::String(s) => s,
+            v => v.to_string(),
+        })
+        .collect())
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Response<T> {
-    pub meta: Value,
+    pub meta: Meta,
     pub data: Vec<T>,
 }
 
@@ -224,4 +675,113 @@ mod tests {
         let want_ids: Vec<&str> = want_ids.split_ascii_whitespace().collect();
         assert_eq!(got_ids, want_ids);
     }
+
+    #[test]
+    fn optimization_history() {
+        let s = read_to_string("testfiles/procedure.json").unwrap();
+        let c: Response<TorsionDriveRecord> = serde_json::from_str(&s).unwrap();
+        for r in c.data.iter().filter(|r| r.status.is_complete()) {
+            let got = r.optimization_history().unwrap();
+            let total: usize =
+                r.optimization_history.values().map(Vec::len).sum();
+            assert_eq!(got.len(), total);
+            // exactly one minimum per grid point, matching the optimizations
+            // used for the conformers
+            let mut minima: Vec<_> = got
+                .iter()
+                .filter(|(_, _, min)| *min)
+                .map(|(_, id, _)| (*id).clone())
+                .collect();
+            assert_eq!(minima.len(), r.minimum_positions.len());
+            let mut want: Vec<_> =
+                r.optimizations().map(|(id, _)| id).collect();
+            minima.sort();
+            want.sort();
+            assert_eq!(minima, want);
+        }
+    }
+
+    #[test]
+    fn grid_optimization_record() {
+        let mut r: GridOptimizationRecord =
+            serde_json::from_value(serde_json::json!({
+                "id": "1",
+                "hash_index": null,
+                "procedure": "gridoptimization",
+                "program": "qcfractal",
+                "version": 1,
+                "protocols": {},
+                "extras": {},
+                "stdout": null,
+                "stderr": null,
+                "error": null,
+                "manager_name": null,
+                "status": "COMPLETE",
+                "modified_on": "2021-01-01T00:00:00",
+                "created_on": "2021-01-01T00:00:00",
+                "provenance": null,
+                "initial_molecule": "10",
+                "starting_molecule": "11",
+                "keywords": {
+                    "scans": [{
+                        "type": "distance",
+                        "indices": [0, 2],
+                        "steps": [-0.1, 0.0, 0.1],
+                        "step_type": "relative",
+                    }],
+                    "preoptimization": true,
+                },
+                "optimization_spec": {"program": "geometric", "keywords": {}},
+                "qc_spec": {
+                    "driver": "gradient",
+                    "method": "b3lyp-d3bj",
+                    "basis": "dzvp",
+                    "keywords": null,
+                    "program": "psi4",
+                },
+                "starting_grid": [1],
+                "final_energy_dict": {
+                    "preoptimization": -2.0,
+                    "[0]": -1.0,
+                    "[1]": -1.5,
+                    "[2]": -1.2,
+                },
+                "grid_optimizations": {
+                    "preoptimization": "19",
+                    "[2]": "22",
+                    "[0]": "20",
+                    "[1]": "21",
+                },
+            }))
+            .unwrap();
+        // the preoptimization isn't a grid point
+        assert_eq!(r.sorted_grid_ids().unwrap(), ["[0]", "[1]", "[2]"]);
+        assert_eq!(r.energy(), Some(-1.5));
+        let mut opts: Vec<_> = r.optimizations().collect();
+        opts.sort();
+        assert_eq!(opts.len(), 3);
+        assert_eq!(opts[0], ("20".into(), ("1".into(), "[0]".into())));
+
+        r.grid_optimizations.insert("[x]".into(), "23".into());
+        assert!(r.sorted_grid_ids().is_err());
+        r.grid_optimizations.remove("[x]");
+
+        r.permute_atoms(&[2, 1, 0]);
+        assert_eq!(r.keywords.scans[0].indices, [2, 0]);
+    }
+
+    #[test]
+    fn meta() {
+        let s = read_to_string("testfiles/procedure.json").unwrap();
+        let c: Response<TorsionDriveRecord> = serde_json::from_str(&s).unwrap();
+        assert!(c.meta.success);
+        assert!(c.meta.errors.is_empty() && c.meta.missing.is_empty());
+        assert_eq!(c.meta.n_found, c.data.len());
+
+        let m: Meta =
+            serde_json::from_str(r#"{"missing": [12, "34"], "n_found": 1}"#)
+                .unwrap();
+        assert!(m.success);
+        assert_eq!(m.missing, ["12", "34"]);
+    }
 }
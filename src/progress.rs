@@ -0,0 +1,156 @@
+//! Progress reporting for long-running requests. [FractalClient] reports the
+//! number of ids in each batch of requests it starts and in each chunk that
+//! completes to an optional [Callback], and [Bars] is a callback drawing those
+//! as progress bars on stderr. Progress is counted in ids rather than chunks
+//! because the chunk sizes change as the requests go. Anything else written to
+//! stderr while the bars are drawn should go through [Bars::writer] so it
+//! doesn't tear them.
+//!
+//! [FractalClient]: crate::client::FractalClient
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use indicatif::{
+    MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+
+/// The stages of retrieving a dataset
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub enum Stage {
+    /// the collections themselves
+    Collection,
+    /// the top-level records in the collections
+    Procedures,
+    /// the optimizations underlying torsion drives and grid optimizations
+    Optimizations,
+    /// final, initial, or other molecules
+    Molecules,
+    /// the records that ended in errors and their error messages
+    Errors,
+    /// full wavefunctions for single-point records
+    Wavefunctions,
+    /// combining the records and molecules into output entries
+    Assembly,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Stage::Collection => "collection",
+            Stage::Procedures => "procedures",
+            Stage::Optimizations => "optimizations",
+            Stage::Molecules => "molecules",
+            Stage::Errors => "errors",
+            Stage::Wavefunctions => "wavefunctions",
+            Stage::Assembly => "assembly",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A progress update from a [FractalClient]
+///
+/// [FractalClient]: crate::client::FractalClient
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// requests for `items` ids were started for `stage`. a stage may be
+    /// started more than once, for example for final and initial molecules,
+    /// in which case the totals should be added together
+    Start { stage: Stage, items: usize },
+
+    /// a chunk of `items` ids in `stage` completed, or was given up on
+    Done { stage: Stage, items: usize },
+}
+
+/// A function receiving progress updates. This is called from whichever task
+/// completed the chunk, so it has to be thread-safe
+pub type Callback = Arc<dyn Fn(Event) + Send + Sync>;
+
+/// Progress bars on stderr, one per [Stage], counting completed ids with
+/// their throughput and an estimated time remaining
+pub struct Bars {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<Stage, ProgressBar>>,
+}
+
+impl Bars {
+    const TEMPLATE: &'static str = "{prefix:>13} [{bar:40}] {pos}/{len} \
+                                    ({per_sec}, eta {eta})";
+
+    /// construct [Bars] drawing on stderr if it's a terminal, otherwise
+    /// `None`
+    pub fn stderr() -> Option<Arc<Self>> {
+        use std::io::IsTerminal;
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        Some(Arc::new(Bars {
+            multi: MultiProgress::with_draw_target(ProgressDrawTarget::stderr()),
+            bars: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// a [Callback] updating `self`
+    pub fn callback(self: &Arc<Self>) -> Callback {
+        let bars = self.clone();
+        Arc::new(move |event| bars.update(event))
+    }
+
+    /// a writer to stderr that hides the bars while it writes
+    pub fn writer(&self) -> StderrWriter {
+        StderrWriter(Some(self.multi.clone()))
+    }
+
+    fn update(&self, event: Event) {
+        let mut bars = self.bars.lock().unwrap();
+        match event {
+            Event::Start { stage, items } => {
+                let bar = bars.entry(stage).or_insert_with(|| {
+                    let style = ProgressStyle::with_template(Self::TEMPLATE)
+                        .unwrap()
+                        .progress_chars("=> ");
+                    let bar = self.multi.add(ProgressBar::new(0));
+                    bar.set_style(style);
+                    bar.set_prefix(stage.to_string());
+                    bar
+                });
+                bar.inc_length(items as u64);
+            }
+            Event::Done { stage, items } => {
+                if let Some(bar) = bars.get(&stage) {
+                    bar.inc(items as u64);
+                    if Some(bar.position()) == bar.length() {
+                        bar.finish();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A writer to stderr that hides any progress bars from [Bars::writer] while
+/// it writes, then redraws them below the new output. The default writes
+/// straight to stderr
+#[derive(Clone, Default)]
+pub struct StderrWriter(Option<MultiProgress>);
+
+impl Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &self.0 {
+            Some(multi) => {
+                multi.suspend(|| io::stderr().write_all(buf))?;
+                Ok(buf.len())
+            }
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
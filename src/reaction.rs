@@ -0,0 +1,222 @@
+//! Reaction energies from QCFractal `ReactionDataset`s. Unlike the other
+//! collections, the entries of a reaction dataset don't point at records but
+//! at molecules, and the single-point energies of those molecules are combined
+//! according to each entry's stoichiometry.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{molecule::Molecule, procedure::Record, result::ResultRecord};
+
+/// An entry in a `ReactionDataset`
+#[derive(Debug, Deserialize)]
+pub struct ReactionEntry {
+    pub name: String,
+
+    /// a map of stoichiometry names, like `default` or `cp` for
+    /// counterpoise-corrected interaction energies, to maps of molecule ids
+    /// to their coefficients in the reaction
+    pub stoichiometry: HashMap<String, HashMap<String, f64>>,
+
+    #[serde(default)]
+    pub attributes: HashMap<String, Value>,
+
+    /// the name of the dataset this entry came from, if known. this is filled
+    /// in by [DataSet::into_reactions]
+    ///
+    /// [DataSet::into_reactions]: crate::collection::DataSet::into_reactions
+    #[serde(skip)]
+    pub dataset: Option<String>,
+}
+
+impl ReactionEntry {
+    /// return the ids of every molecule in any of the entry's stoichiometries
+    pub fn molecule_ids(&self) -> impl Iterator<Item = &String> {
+        self.stoichiometry.values().flat_map(HashMap::keys)
+    }
+}
+
+/// One molecule's contribution to a [ReactionResult]
+#[derive(Debug, Serialize)]
+pub struct Component {
+    pub molecule_id: String,
+    pub molecular_formula: String,
+    pub coefficient: f64,
+    pub energy: f64,
+}
+
+/// The energy of one reaction for one stoichiometry and QC specification
+#[derive(Debug, Serialize)]
+pub struct ReactionResult {
+    pub name: String,
+    pub stoichiometry: String,
+    pub method: String,
+    pub basis: Option<String>,
+    pub program: String,
+    pub keywords: Option<String>,
+
+    /// the sum of the component energies weighted by their coefficients
+    pub energy: f64,
+    pub components: Vec<Component>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>,
+}
+
+impl ReactionResult {
+    /// multiply the reaction energy and those of its components by `factor`
+    pub fn scale_energies(&mut self, factor: f64) {
+        self.energy *= factor;
+        for c in &mut self.components {
+            c.energy *= factor;
+        }
+    }
+}
+
+/// the fields of a [QCSpecification] identifying distinct results on the same
+/// molecule
+///
+/// [QCSpecification]: crate::procedure::QCSpecification
+type SpecKey = (String, Option<String>, String, Option<String>);
+
+fn spec_key(record: &ResultRecord) -> SpecKey {
+    let spec = record.qc_spec();
+    (
+        spec.method.clone(),
+        spec.basis.clone(),
+        spec.program.clone(),
+        spec.keywords.clone(),
+    )
+}
+
+/// combine the energies in `records` into a [ReactionResult] for each of
+/// `entries`, each of their stoichiometries, and each QC specification in
+/// `records`. reactions missing the energy of any of their molecules for a
+/// given specification are skipped
+pub fn reaction_energies(
+    entries: &[ReactionEntry],
+    records: &[ResultRecord],
+    molecules: &HashMap<String, Molecule>,
+) -> Vec<ReactionResult> {
+    // spec -> molecule id -> energy. a BTreeMap keeps the output order stable
+    let mut energies: BTreeMap<SpecKey, HashMap<&str, f64>> = BTreeMap::new();
+    for r in records {
+        if let Some(e) = r.energy() {
+            energies
+                .entry(spec_key(r))
+                .or_default()
+                .insert(&r.molecule, e);
+        }
+    }
+
+    let mut ret = Vec::new();
+    let mut skipped = 0;
+    for entry in entries {
+        let mut stoichiometries: Vec<_> = entry.stoichiometry.iter().collect();
+        stoichiometries.sort_by_key(|(name, _)| *name);
+        for (stoich, coefs) in stoichiometries {
+            for ((method, basis, program, keywords), mol_energies) in &energies
+            {
+                let components: Option<Vec<Component>> = coefs
+                    .iter()
+                    .map(|(id, &coefficient)| {
+                        Some(Component {
+                            molecule_id: id.clone(),
+                            molecular_formula: molecules
+                                .get(id)
+                                .map(|m| {
+                                    m.identifiers.molecular_formula.clone()
+                                })
+                                .unwrap_or_default(),
+                            coefficient,
+                            energy: *mol_energies.get(id.as_str())?,
+                        })
+                    })
+                    .collect();
+                let Some(mut components) = components else {
+                    skipped += 1;
+                    continue;
+                };
+                components.sort_by(|a, b| a.molecule_id.cmp(&b.molecule_id));
+                ret.push(ReactionResult {
+                    name: entry.name.clone(),
+                    stoichiometry: stoich.clone(),
+                    method: method.clone(),
+                    basis: basis.clone(),
+                    program: program.clone(),
+                    keywords: keywords.clone(),
+                    energy: components
+                        .iter()
+                        .map(|c| c.coefficient * c.energy)
+                        .sum(),
+                    components,
+                    dataset: entry.dataset.clone(),
+                });
+            }
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            "skipped {skipped} reaction/specification pairs missing energies"
+        );
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        id: &str,
+        molecule: &str,
+        method: &str,
+        energy: f64,
+    ) -> ResultRecord {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "status": "COMPLETE",
+            "driver": "energy",
+            "method": method,
+            "basis": "dzvp",
+            "program": "psi4",
+            "keywords": null,
+            "molecule": molecule,
+            "return_result": energy,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn energies() {
+        // A + B -> AB
+        let entries = vec![ReactionEntry {
+            name: "dimer".to_owned(),
+            stoichiometry: HashMap::from([(
+                "default".to_owned(),
+                HashMap::from([
+                    ("1".to_owned(), -1.0),
+                    ("2".to_owned(), -1.0),
+                    ("3".to_owned(), 1.0),
+                ]),
+            )]),
+            attributes: HashMap::new(),
+            dataset: None,
+        }];
+        let records = vec![
+            record("10", "1", "hf", -1.0),
+            record("11", "2", "hf", -2.0),
+            record("12", "3", "hf", -3.5),
+            // only one molecule has an mp2 energy, so no reaction for it
+            record("13", "1", "mp2", -1.1),
+        ];
+        let got = reaction_energies(&entries, &records, &HashMap::new());
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].method, "hf");
+        assert_eq!(got[0].energy, -0.5);
+        assert_eq!(got[0].components.len(), 3);
+    }
+}
@@ -0,0 +1,151 @@
+//! Triage of failed records. The `error` and `stdout` fields on a record are
+//! only ids into the key-value store, so [FractalClient::error_report] fetches
+//! those and uses them to build a sequence of [ErrorEntry]s.
+//!
+//! [FractalClient::error_report]: crate::client::FractalClient::error_report
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{kvstore::KVStore, procedure::FailedRecord};
+
+/// rough categories of QC failures, determined by matching known messages in
+/// the error and stdout of a record
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize,
+)]
+pub enum ErrorCategory {
+    /// the SCF failed to converge
+    ScfConvergence,
+    /// the geometry optimizer ran out of iterations
+    IterationLimit,
+    /// the manager running the calculation stopped responding
+    ManagerLost,
+    /// the calculation ran out of memory
+    Memory,
+    /// none of the above, or the error couldn't be retrieved
+    Unknown,
+}
+
+impl ErrorCategory {
+    /// classify a failure from the text of its error message and stdout
+    pub fn classify(text: &str) -> Self {
+        const PATTERNS: [(&str, ErrorCategory); 8] = [
+            ("could not converge scf", ErrorCategory::ScfConvergence),
+            ("scfconvergenceerror", ErrorCategory::ScfConvergence),
+            (
+                "maximum number of optimization cycles",
+                ErrorCategory::IterationLimit,
+            ),
+            ("maximum iterations reached", ErrorCategory::IterationLimit),
+            ("manager", ErrorCategory::ManagerLost),
+            ("memoryerror", ErrorCategory::Memory),
+            ("out of memory", ErrorCategory::Memory),
+            ("not enough memory", ErrorCategory::Memory),
+        ];
+        let text = text.to_lowercase();
+        for (pat, cat) in PATTERNS {
+            // "manager" alone is too common in tracebacks, so require the
+            // manager to actually have been lost
+            if cat == ErrorCategory::ManagerLost && !text.contains("lost") {
+                continue;
+            }
+            if text.contains(pat) {
+                return cat;
+            }
+        }
+        Self::Unknown
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorEntry {
+    pub record_id: String,
+    pub procedure: String,
+    pub manager_name: Option<String>,
+    pub category: ErrorCategory,
+    pub error_type: Option<String>,
+    pub error_message: Option<String>,
+    pub stdout: Option<String>,
+}
+
+impl ErrorEntry {
+    /// construct an [ErrorEntry] from `record`, looking up its error and
+    /// stdout in `blobs`
+    pub fn new(record: FailedRecord, blobs: &HashMap<String, KVStore>) -> Self {
+        let error = record.error.as_ref().and_then(|id| blobs.get(id));
+        let (error_type, error_message) =
+            match error.and_then(KVStore::as_compute_error) {
+                Some(e) => (Some(e.error_type), Some(e.error_message)),
+                None => (None, error.and_then(KVStore::as_string)),
+            };
+        let stdout = record
+            .stdout
+            .as_ref()
+            .and_then(|id| blobs.get(id))
+            .and_then(KVStore::as_string);
+
+        // prefer the error message, but fall back on the stdout if that didn't
+        // turn up anything
+        let mut category = ErrorCategory::classify(&format!(
+            "{} {}",
+            error_type.as_deref().unwrap_or_default(),
+            error_message.as_deref().unwrap_or_default()
+        ));
+        if category == ErrorCategory::Unknown {
+            if let Some(stdout) = &stdout {
+                category = ErrorCategory::classify(stdout);
+            }
+        }
+
+        Self {
+            record_id: record.id,
+            procedure: record.procedure,
+            manager_name: record.manager_name,
+            category,
+            error_type,
+            error_message,
+            stdout,
+        }
+    }
+}
+
+/// count the number of entries in each [ErrorCategory]
+pub fn summarize(entries: &[ErrorEntry]) -> Vec<(ErrorCategory, usize)> {
+    let mut counts = HashMap::new();
+    for entry in entries {
+        *counts.entry(entry.category).or_insert(0) += 1;
+    }
+    let mut ret: Vec<_> = counts.into_iter().collect();
+    ret.sort();
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        use ErrorCategory::*;
+        let tests = [
+            (
+                "Could not converge SCF iterations in 100 iterations.",
+                ScfConvergence,
+            ),
+            (
+                "geomeTRIC run_json error: Maximum number of optimization \
+                 cycles reached",
+                IterationLimit,
+            ),
+            ("Task manager `psi4-abc` was lost", ManagerLost),
+            ("Traceback: File manager.py, line 10", Unknown),
+            ("MemoryError", Memory),
+            ("something else entirely", Unknown),
+        ];
+        for (text, want) in tests {
+            assert_eq!(ErrorCategory::classify(text), want, "{text}");
+        }
+    }
+}
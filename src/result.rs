@@ -0,0 +1,469 @@
+//! [FractalClient] queries for single-point [ResultRecord]s, the records
+//! behind single-point datasets.
+//!
+//! [FractalClient]: crate::client::FractalClient
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    client::Body,
+    filter::SpecFilter,
+    procedure::{Driver, QCSpecification, Record},
+    Status,
+};
+
+#[derive(Default, Serialize)]
+struct QueryFilter {
+    include: Option<usize>,
+    exclude: Option<usize>,
+    limit: Option<usize>,
+    skip: usize,
+}
+
+#[derive(Serialize)]
+struct Data {
+    id: Option<Vec<String>>,
+    task_id: Option<usize>,
+    program: Option<String>,
+    molecule: Option<Vec<String>>,
+    driver: Option<Driver>,
+    method: Option<String>,
+    basis: Option<String>,
+    keywords: Option<String>,
+    status: Status,
+}
+
+#[derive(Serialize)]
+pub struct ResultGetBody {
+    meta: QueryFilter,
+    data: Data,
+}
+
+impl ResultGetBody {
+    /// request results with `status` instead of the default
+    /// [Status::Complete]
+    pub fn with_status(mut self, status: Status) -> Self {
+        self.data.status = status;
+        self
+    }
+
+    /// request the results computed on the molecules with the ids passed to
+    /// [Body::new], instead of the results with those ids. this is how
+    /// single-point datasets refer to their records
+    pub fn by_molecule(mut self) -> Self {
+        self.data.molecule = self.data.id.take();
+        self
+    }
+
+    /// only request results matching the fields set in `spec`. a basis of
+    /// `none` is not sent, since the server can't query for a null basis, so
+    /// the results should still be checked with [SpecFilter::apply]
+    pub fn with_spec(mut self, spec: &SpecFilter) -> Self {
+        let lower = |s: &Option<String>| s.as_ref().map(|s| s.to_lowercase());
+        self.data.program = lower(&spec.program);
+        self.data.method = lower(&spec.method);
+        self.data.basis =
+            lower(&spec.basis).filter(|b| !b.eq_ignore_ascii_case("none"));
+        self.data.driver = spec.driver;
+        self
+    }
+}
+
+impl Body for ResultGetBody {
+    fn new(ids: Vec<String>) -> Self {
+        Self {
+            meta: QueryFilter::default(),
+            data: Data {
+                id: Some(ids),
+                task_id: None,
+                program: None,
+                molecule: None,
+                driver: None,
+                method: None,
+                basis: None,
+                keywords: None,
+                status: Status::Complete,
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WavefunctionData {
+    id: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct WavefunctionGetBody {
+    meta: QueryFilter,
+    data: WavefunctionData,
+}
+
+impl Body for WavefunctionGetBody {
+    fn new(id: Vec<String>) -> Self {
+        Self {
+            meta: QueryFilter::default(),
+            data: WavefunctionData { id },
+        }
+    }
+}
+
+/// Short names for commonly requested properties and where to find them,
+/// either in [ResultRecord::properties] or in the qcvars in its extras. Names
+/// not in this table are looked up as they are. The bool is whether the
+/// property has one value per atom
+const PROPERTY_ALIASES: [(&str, &[&str], bool); 4] = [
+    ("dipole", &["scf_dipole_moment", "current dipole"], false),
+    (
+        "quadrupole",
+        &["scf_quadrupole_moment", "current quadrupole"],
+        false,
+    ),
+    ("mbis_charges", &["mbis charges"], true),
+    ("mulliken_charges", &["mulliken charges"], true),
+];
+
+/// A single-point calculation on one molecule
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResultRecord {
+    // base identification
+    pub id: String,
+    pub procedure: Option<String>,
+    pub version: Option<usize>,
+    pub extras: Option<HashMap<String, Value>>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub error: Option<String>,
+    pub manager_name: Option<String>,
+    pub status: Status,
+    pub modified_on: Option<String>,
+    pub created_on: Option<String>,
+    pub provenance: Option<Value>,
+
+    // input data. unlike the procedures, the specification is stored
+    // directly on the record
+    #[serde(flatten)]
+    pub qc_spec: QCSpecification,
+    pub molecule: String,
+
+    // output data
+    /// the energy, gradient, Hessian, etc. computed, depending on the driver.
+    /// gradients and Hessians are flattened
+    #[serde(default)]
+    pub return_result: Value,
+    #[serde(default)]
+    pub properties: HashMap<String, Value>,
+
+    /// a summary of the wavefunction stored for the record, if its
+    /// `wavefunction` protocol kept one
+    #[serde(default)]
+    pub wavefunction: Option<Value>,
+
+    /// the id of the full wavefunction in the server's wavefunction store
+    #[serde(default)]
+    pub wavefunction_data_id: Option<String>,
+
+    /// the full wavefunction, filled in by
+    /// [FractalClient::wavefunctions] when requested
+    ///
+    /// [FractalClient::wavefunctions]: crate::client::FractalClient::wavefunctions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wavefunction_data: Option<Value>,
+
+    /// the properties requested with [Self::select_properties], keyed by the
+    /// names they were requested with
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub selected_properties: BTreeMap<String, Value>,
+
+    /// `return_result` reshaped to one row of 3 components per atom, if the
+    /// driver is [Driver::Gradient]. this is filled in by [Self::reshape]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<Vec<[f64; 3]>>,
+
+    /// `return_result` reshaped to a 3N x 3N matrix, if the driver is
+    /// [Driver::Hessian]. this is filled in by [Self::reshape]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hessian: Option<Vec<Vec<f64>>>,
+}
+
+/// the numbers in `value`, which should either be a single number or an array
+/// of them
+fn numbers(value: &Value) -> Vec<f64> {
+    match value {
+        Value::Array(v) => v.iter().filter_map(Value::as_f64).collect(),
+        v => v.as_f64().into_iter().collect(),
+    }
+}
+
+/// multiply all of the numbers in `value` by `factor`
+fn scale_value(value: &mut Value, factor: f64) {
+    match value {
+        Value::Number(n) => {
+            if let Some(x) = n.as_f64() {
+                *value = Value::from(x * factor);
+            }
+        }
+        Value::Array(v) => v.iter_mut().for_each(|x| scale_value(x, factor)),
+        _ => {}
+    }
+}
+
+impl ResultRecord {
+    /// whether the record's `wavefunction` protocol kept a wavefunction that
+    /// can be requested from the server
+    pub fn has_wavefunction(&self) -> bool {
+        let kept = self
+            .qc_spec
+            .protocols
+            .get("wavefunction")
+            .and_then(Value::as_str)
+            .is_some_and(|w| w != "none");
+        kept && self.wavefunction_data_id.is_some()
+    }
+
+    /// look up the property `name` in the record, checking the aliases in
+    /// [PROPERTY_ALIASES] first. qcvars are matched case-insensitively, with
+    /// underscores treated as spaces
+    fn find_property(&self, name: &str) -> Option<&Value> {
+        let name = name.to_lowercase();
+        let keys = PROPERTY_ALIASES
+            .iter()
+            .find(|(alias, _, _)| *alias == name)
+            .map_or_else(|| vec![name.as_str()], |(_, keys, _)| keys.to_vec());
+        let qcvars = self
+            .extras
+            .as_ref()
+            .and_then(|e| e.get("qcvars")?.as_object());
+        keys.into_iter().find_map(|key| {
+            if let Some(v) = self.properties.get(key).filter(|v| !v.is_null()) {
+                return Some(v);
+            }
+            let key = key.replace('_', " ");
+            qcvars?
+                .iter()
+                .find(|(k, _)| k.to_lowercase().replace('_', " ") == key)
+                .map(|(_, v)| v)
+        })
+    }
+
+    /// copy the properties in `names` into [Self::selected_properties],
+    /// warning about any that aren't available
+    pub fn select_properties(&mut self, names: &[String]) {
+        for name in names {
+            match self.find_property(name).cloned() {
+                Some(v) => {
+                    self.selected_properties.insert(name.clone(), v);
+                }
+                None => {
+                    warn!("property `{name}` not found for record {}", self.id)
+                }
+            }
+        }
+    }
+
+    /// fill in [Self::gradient] or [Self::hessian] from `return_result`
+    /// according to the driver. this should be called again after modifying
+    /// `return_result`
+    pub fn reshape(&mut self) {
+        let flat = numbers(&self.return_result);
+        match self.qc_spec.driver {
+            Driver::Gradient if flat.len().is_multiple_of(3) => {
+                self.gradient = Some(
+                    flat.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+                );
+            }
+            Driver::Hessian => {
+                let n = (flat.len() as f64).sqrt() as usize;
+                if n * n == flat.len() && n > 0 {
+                    self.hessian = Some(
+                        flat.chunks_exact(n).map(<[f64]>::to_vec).collect(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Record for ResultRecord {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn initial_molecules(&self) -> Vec<String> {
+        vec![self.molecule.clone()]
+    }
+
+    fn energy(&self) -> Option<f64> {
+        match self.qc_spec.driver {
+            Driver::Energy => self.return_result.as_f64(),
+            _ => self.properties.get("return_energy")?.as_f64(),
+        }
+    }
+
+    fn qc_spec(&self) -> &QCSpecification {
+        &self.qc_spec
+    }
+
+    fn scale_energies(&mut self, factor: f64) {
+        if self.qc_spec.driver != Driver::Properties {
+            scale_value(&mut self.return_result, factor);
+        }
+        for (k, v) in &mut self.properties {
+            if k.ends_with("energy") {
+                scale_value(v, factor);
+            }
+        }
+        self.reshape();
+    }
+
+    fn scale_lengths(&mut self, factor: f64) {
+        let factor = match self.qc_spec.driver {
+            Driver::Gradient => factor,
+            Driver::Hessian => factor * factor,
+            _ => return,
+        };
+        scale_value(&mut self.return_result, 1.0 / factor);
+        self.reshape();
+    }
+
+    fn permute_atoms(&mut self, order: &[usize]) {
+        for (name, value) in &mut self.selected_properties {
+            let per_atom =
+                PROPERTY_ALIASES.iter().any(|(alias, _, per_atom)| {
+                    *per_atom && alias.eq_ignore_ascii_case(name)
+                });
+            if let Value::Array(v) = value {
+                if per_atom && v.len() == order.len() {
+                    *v = order.iter().map(|&i| v[i].clone()).collect();
+                }
+            }
+        }
+
+        let flat = numbers(&self.return_result);
+        let new: Vec<f64> = match self.qc_spec.driver {
+            Driver::Gradient if flat.len() == 3 * order.len() => order
+                .iter()
+                .flat_map(|&i| flat[3 * i..3 * i + 3].to_vec())
+                .collect(),
+            Driver::Hessian if flat.len() == 9 * order.len().pow(2) => {
+                let n = 3 * order.len();
+                let old = |r: usize| 3 * order[r / 3] + r % 3;
+                (0..n)
+                    .flat_map(|r| (0..n).map(move |c| (r, c)))
+                    .map(|(r, c)| flat[old(r) * n + old(c)])
+                    .collect()
+            }
+            _ => return,
+        };
+        self.return_result = new.into();
+        self.reshape();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(driver: &str, return_result: Value) -> ResultRecord {
+        let mut ret: ResultRecord = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "status": "COMPLETE",
+            "driver": driver,
+            "method": "b3lyp-d3bj",
+            "basis": "dzvp",
+            "program": "psi4",
+            "keywords": null,
+            "molecule": "2",
+            "return_result": return_result,
+            "properties": {"return_energy": -1.0},
+        }))
+        .unwrap();
+        ret.reshape();
+        ret
+    }
+
+    #[test]
+    fn gradient() {
+        let mut r = record("gradient", (0..6).map(f64::from).collect());
+        assert_eq!(r.gradient, Some(vec![[0.0, 1.0, 2.0], [3.0, 4.0, 5.0]]));
+        assert_eq!(r.energy(), Some(-1.0));
+
+        r.permute_atoms(&[1, 0]);
+        assert_eq!(r.gradient, Some(vec![[3.0, 4.0, 5.0], [0.0, 1.0, 2.0]]));
+
+        r.scale_energies(2.0);
+        r.scale_lengths(0.5);
+        assert_eq!(r.gradient.as_ref().unwrap()[0], [12.0, 16.0, 20.0]);
+        assert_eq!(r.energy(), Some(-2.0));
+    }
+
+    #[test]
+    fn hessian() {
+        // two atoms, with each element encoding its row and column
+        let n = 6;
+        let flat: Vec<f64> =
+            (0..n * n).map(|i| (10 * (i / n) + i % n) as f64).collect();
+        let mut r = record("hessian", flat.into());
+        let h = r.hessian.clone().unwrap();
+        assert_eq!(h.len(), 6);
+        assert_eq!(h[1][4], 14.0);
+
+        r.permute_atoms(&[1, 0]);
+        let h = r.hessian.clone().unwrap();
+        // new row 1 is old row 4, new column 4 is old column 1
+        assert_eq!(h[1][4], 41.0);
+        assert_eq!(h[3][3], 0.0);
+
+        r.scale_lengths(2.0);
+        assert_eq!(r.hessian.unwrap()[1][4], 41.0 / 4.0);
+    }
+
+    #[test]
+    fn properties() {
+        let mut r = record("energy", (-1.0).into());
+        r.properties.insert(
+            "scf_dipole_moment".to_owned(),
+            serde_json::json!([0.1, 0.2, 0.3]),
+        );
+        r.extras = Some(HashMap::from([(
+            "qcvars".to_owned(),
+            serde_json::json!({"MBIS CHARGES": [-0.8, 0.4, 0.4]}),
+        )]));
+        let names = ["dipole", "MBIS_CHARGES", "missing"].map(String::from);
+        r.select_properties(&names);
+        assert_eq!(r.selected_properties.len(), 2);
+        assert_eq!(
+            r.selected_properties["dipole"],
+            serde_json::json!([0.1, 0.2, 0.3])
+        );
+
+        r.permute_atoms(&[1, 0, 2]);
+        assert_eq!(
+            r.selected_properties["MBIS_CHARGES"],
+            serde_json::json!([0.4, -0.8, 0.4])
+        );
+        assert!(!r.has_wavefunction());
+    }
+
+    #[test]
+    fn error_query() {
+        let spec = SpecFilter {
+            method: Some("B3LYP-D3BJ".to_owned()),
+            ..Default::default()
+        };
+        let body = ResultGetBody::new(vec!["2".to_owned()])
+            .with_status(Status::Error)
+            .by_molecule()
+            .with_spec(&spec);
+        let got = serde_json::to_value(&body).unwrap();
+        assert_eq!(got["data"]["status"], "ERROR");
+        assert_eq!(got["data"]["molecule"], serde_json::json!(["2"]));
+        assert_eq!(got["data"]["id"], Value::Null);
+        assert_eq!(got["data"]["method"], "b3lyp-d3bj");
+    }
+}
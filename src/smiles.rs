@@ -0,0 +1,291 @@
+//! A minimal SMILES parser, just complete enough to read the atoms and bonds
+//! out of the mapped, explicit-hydrogen SMILES stored as `cmiles` in QCArchive
+//! datasets. Stereochemistry is skipped, and bond orders are not retained.
+
+use std::collections::{HashMap, HashSet};
+
+/// the organic subset of elements that can appear outside of brackets, with
+/// the two-letter symbols first so they are matched before their prefixes
+const ORGANIC: [&str; 16] = [
+    "Cl", "Br", "B", "C", "N", "O", "P", "S", "F", "I", "b", "c", "n", "o",
+    "p", "s",
+];
+
+/// aromatic symbols allowed inside of brackets
+const AROMATIC: [&str; 9] = ["se", "as", "te", "b", "c", "n", "o", "p", "s"];
+
+#[derive(Debug, PartialEq)]
+pub struct Atom {
+    /// the element symbol, in title case even for aromatic atoms
+    pub symbol: String,
+    pub aromatic: bool,
+    pub charge: isize,
+
+    /// the atom map number, if any
+    pub map: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct Smiles {
+    pub atoms: Vec<Atom>,
+
+    /// pairs of indices into `atoms`
+    pub bonds: Vec<(usize, usize)>,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    s: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Self {
+        Self {
+            chars: s.char_indices().peekable(),
+            s,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn pos(&mut self) -> usize {
+        self.chars.peek().map(|(i, _)| *i).unwrap_or(self.s.len())
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos();
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.chars.next();
+        }
+        let end = self.pos();
+        self.s[start..end].parse().ok()
+    }
+
+    /// consume the longest of `candidates` that the remaining input starts
+    /// with
+    fn symbol(&mut self, candidates: &[&str]) -> Option<String> {
+        let pos = self.pos();
+        let rest = &self.s[pos..];
+        let sym = candidates.iter().find(|c| rest.starts_with(**c))?;
+        for _ in 0..sym.len() {
+            self.chars.next();
+        }
+        Some(sym.to_string())
+    }
+
+    /// parse a bracket atom, assuming the opening `[` has been consumed
+    fn bracket_atom(&mut self) -> Result<Atom, String> {
+        // isotope
+        self.number();
+
+        let (symbol, aromatic) = if let Some(sym) = self.symbol(&AROMATIC) {
+            (title_case(&sym), true)
+        } else {
+            let mut sym = String::new();
+            match self.chars.next() {
+                Some((_, c)) if c.is_ascii_uppercase() => sym.push(c),
+                c => {
+                    return Err(format!("expected element symbol, found {c:?}"))
+                }
+            }
+            if let Some(c) = self.peek().filter(char::is_ascii_lowercase) {
+                sym.push(c);
+                self.chars.next();
+            }
+            (sym, false)
+        };
+
+        // chirality
+        while self.peek() == Some('@') {
+            self.chars.next();
+        }
+
+        // hydrogen count. these are all explicit in cmiles, but skip it
+        // anyway
+        if self.peek() == Some('H') {
+            self.chars.next();
+            self.number();
+        }
+
+        let mut charge = 0;
+        while let Some(c @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            let sign = if c == '+' { 1 } else { -1 };
+            charge += sign * self.number().unwrap_or(1) as isize;
+        }
+
+        let map = if self.peek() == Some(':') {
+            self.chars.next();
+            Some(self.number().ok_or("expected atom map number")?)
+        } else {
+            None
+        };
+
+        match self.chars.next() {
+            Some((_, ']')) => Ok(Atom {
+                symbol,
+                aromatic,
+                charge,
+                map,
+            }),
+            c => Err(format!("expected `]`, found {c:?}")),
+        }
+    }
+}
+
+pub(crate) fn title_case(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// parse the SMILES string `s`
+pub fn parse(s: &str) -> Result<Smiles, String> {
+    let mut p = Parser::new(s);
+    let mut atoms = Vec::new();
+    let mut bonds = Vec::new();
+
+    // the index of the atom the next atom should be bonded to
+    let mut prev: Option<usize> = None;
+    let mut branches = Vec::new();
+    let mut rings: HashMap<usize, usize> = HashMap::new();
+
+    while let Some(c) = p.peek() {
+        match c {
+            '[' => {
+                p.chars.next();
+                atoms.push(p.bracket_atom()?);
+            }
+            '(' => {
+                p.chars.next();
+                branches.push(prev.ok_or("branch before first atom")?);
+                continue;
+            }
+            ')' => {
+                p.chars.next();
+                prev = Some(branches.pop().ok_or("unmatched `)`")?);
+                continue;
+            }
+            '-' | '=' | '#' | '$' | ':' | '/' | '\\' => {
+                p.chars.next();
+                continue;
+            }
+            '.' => {
+                p.chars.next();
+                prev = None;
+                continue;
+            }
+            '%' | '0'..='9' => {
+                let n = if c == '%' {
+                    p.chars.next();
+                    p.number().ok_or("expected ring closure number")?
+                } else {
+                    p.chars.next();
+                    c.to_digit(10).unwrap() as usize
+                };
+                let cur = prev.ok_or("ring closure before first atom")?;
+                match rings.remove(&n) {
+                    Some(other) => bonds.push((other, cur)),
+                    None => {
+                        rings.insert(n, cur);
+                    }
+                }
+                continue;
+            }
+            _ => {
+                let sym = p
+                    .symbol(&ORGANIC)
+                    .ok_or_else(|| format!("unexpected `{c}` in SMILES"))?;
+                let aromatic = sym.chars().next().unwrap().is_lowercase();
+                atoms.push(Atom {
+                    symbol: title_case(&sym),
+                    aromatic,
+                    charge: 0,
+                    map: None,
+                });
+            }
+        }
+        // only atoms make it here
+        let cur = atoms.len() - 1;
+        if let Some(prev) = prev {
+            bonds.push((prev, cur));
+        }
+        prev = Some(cur);
+    }
+
+    if !branches.is_empty() {
+        return Err("unmatched `(`".to_owned());
+    }
+    if !rings.is_empty() {
+        return Err("unclosed ring".to_owned());
+    }
+
+    Ok(Smiles { atoms, bonds })
+}
+
+impl Smiles {
+    /// return the bonds in terms of zero-based atom map indices, with the
+    /// smaller index first in each pair. returns an error if any atoms are not
+    /// mapped
+    pub fn mapped_bonds(&self) -> Result<HashSet<(usize, usize)>, String> {
+        let map = |i: usize| {
+            self.atoms[i]
+                .map
+                .and_then(|m| m.checked_sub(1))
+                .ok_or_else(|| format!("atom {i} is not mapped"))
+        };
+        self.bonds
+            .iter()
+            .map(|&(a, b)| {
+                let (a, b) = (map(a)?, map(b)?);
+                Ok((a.min(b), a.max(b)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cmiles() {
+        let s = "[H:12][c:1]1[c:2]([c:3]([c:4]([c:5]([c:6]1[H:16])[H:15])\
+                 [S:7](=[O:8])(=[O:9])[N:10]([H:17])[N:11]([H:18])[H:19])\
+                 [H:14])[H:13]";
+        let got = parse(s).unwrap();
+        assert_eq!(got.atoms.len(), 19);
+        // one ring
+        assert_eq!(got.bonds.len(), 19);
+        assert_eq!(
+            got.atoms[1],
+            Atom {
+                symbol: "C".to_owned(),
+                aromatic: true,
+                charge: 0,
+                map: Some(1),
+            }
+        );
+        let bonds = got.mapped_bonds().unwrap();
+        // the ring closure
+        assert!(bonds.contains(&(0, 5)));
+        // H:12 bonded to c:1
+        assert!(bonds.contains(&(0, 11)));
+        assert!(bonds.contains(&(6, 7)));
+    }
+
+    #[test]
+    fn parse_unmapped() {
+        let got = parse("C[N+](C)(C)CCl.[Cl-]").unwrap();
+        let symbols: Vec<_> = got.atoms.iter().map(|a| &a.symbol).collect();
+        assert_eq!(symbols, ["C", "N", "C", "C", "C", "Cl", "Cl"]);
+        assert_eq!(got.atoms[1].charge, 1);
+        assert_eq!(got.atoms[6].charge, -1);
+        assert_eq!(got.bonds, [(0, 1), (1, 2), (1, 3), (1, 4), (4, 5)]);
+        assert!(got.mapped_bonds().is_err());
+    }
+}
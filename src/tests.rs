@@ -1,11 +1,18 @@
-use std::fs::read_to_string;
+use std::{collections::HashMap, fs::read_to_string};
 
 use openff_toolkit::qcsubmit::results::TorsionDriveResultCollection;
 
 use crate::{
     client::FractalClient,
-    collection::{CollectionGetBody, CollectionGetResponse, CollectionType},
+    collection::{
+        group_by_server, CollectionGetBody, CollectionGetResponse,
+        CollectionType, ResultCollection,
+    },
+    filter::SpecFilter,
+    make_opt_results,
+    molecule::Molecule,
     procedure::{OptimizationRecord, Response, TorsionDriveRecord},
+    ResultEntry,
 };
 
 #[test]
@@ -15,6 +22,100 @@ fn de_response() {
     dbg!(c);
 }
 
+#[test]
+fn merge_responses() {
+    let s = read_to_string("testfiles/response.json").unwrap();
+    let a: CollectionGetResponse = serde_json::from_str(&s).unwrap();
+    let b: CollectionGetResponse = serde_json::from_str(&s).unwrap();
+    let want = a.ids();
+    let got = CollectionGetResponse::merge([a, b]);
+    assert_eq!(got.data.len(), 2);
+    assert_eq!(got.ids(), want);
+    let name = "OpenFF multiplicity correction torsion drive data v1.1";
+    assert!(got
+        .into_results()
+        .iter()
+        .all(|r| r.dataset.as_deref() == Some(name)));
+}
+
+#[test]
+fn multi_server() {
+    let ds =
+        ResultCollection::parse_file("testfiles/multi-server.json").unwrap();
+    let got: Vec<_> = group_by_server(ds)
+        .into_iter()
+        .map(|(addr, col)| {
+            let mut ids = col.ids();
+            ids.sort();
+            (addr, ids)
+        })
+        .collect();
+    let want = vec![
+        ("http://localhost:7777".to_owned(), vec!["1".to_owned()]),
+        (
+            "https://api.qcarchive.molssi.org:443/".to_owned(),
+            vec!["104321523".to_owned(), "107276605".to_owned()],
+        ),
+    ];
+    assert_eq!(got, want);
+}
+
+#[test]
+fn save_collection() {
+    let s = read_to_string("testfiles/response.json").unwrap();
+    let c: CollectionGetResponse = serde_json::from_str(&s).unwrap();
+    let s = read_to_string("testfiles/core-td.json").unwrap();
+    let mut want: ResultCollection = serde_json::from_str(&s).unwrap();
+
+    // core-td.json only contains the complete records
+    let addr = FractalClient::DEFAULT_ADDRESS;
+    let want_entries = want.entries.get_mut(addr).unwrap();
+    let mut got = ResultCollection::new(CollectionType::TorsionDrive, addr, &c);
+    got.retain_records(want_entries.iter().map(|e| &e.record_id));
+    want_entries.sort_by(|a, b| a.record_id.cmp(&b.record_id));
+
+    assert_eq!(got.typ, want.typ);
+    assert_eq!(got.entries, want.entries);
+}
+
+#[test]
+fn save_single_point_collection() {
+    // single-point datasets only list their molecules, so the saved
+    // collection comes from the retrieved records
+    let record = |id: &str| (id.to_owned(), format!("C{id}"), format!("K{id}"));
+    let got = ResultCollection::from_records(
+        CollectionType::SinglePoint,
+        FractalClient::DEFAULT_ADDRESS,
+        [record("2"), record("1"), record("2")],
+    );
+    assert_eq!(got.collection_type().unwrap(), CollectionType::SinglePoint);
+    let entries = &got.entries[FractalClient::DEFAULT_ADDRESS];
+    let ids: Vec<_> = entries.iter().map(|e| e.record_id.as_str()).collect();
+    assert_eq!(ids, ["1", "2"]);
+    assert_eq!(entries[0].cmiles, "C1");
+    assert_eq!(entries[0].inchi_key, "K1");
+}
+
+#[test]
+fn result_collection_type() {
+    for (file, want) in [
+        ("testfiles/core-td.json", CollectionType::TorsionDrive),
+        ("testfiles/core-opt.json", CollectionType::Optimization),
+    ] {
+        let got = ResultCollection::parse_file(file)
+            .unwrap()
+            .collection_type()
+            .unwrap();
+        assert_eq!(got, want);
+    }
+
+    let mut ds =
+        ResultCollection::parse_file("testfiles/core-td.json").unwrap();
+    ds.entries.values_mut().next().unwrap()[3].typ = "optimization".into();
+    let got = ds.collection_type().unwrap_err();
+    assert!(got.contains("has type `optimization`"), "{got}");
+}
+
 #[test]
 fn de_singlept_response() {
     let s = read_to_string("testfiles/singlept_collection.json").unwrap();
@@ -36,6 +137,71 @@ fn de_opt_procedure() {
     dbg!(c);
 }
 
+#[test]
+fn initial_conformers() {
+    let s = read_to_string("testfiles/opt_procedure.json").unwrap();
+    let c: Response<OptimizationRecord> = serde_json::from_str(&s).unwrap();
+    let s = read_to_string("testfiles/molecules.json").unwrap();
+    let m: Response<Molecule> = serde_json::from_str(&s).unwrap();
+    let mol = m.data.into_iter().next().unwrap();
+    let mut entry = ResultEntry {
+        record: c.data.into_iter().next().unwrap(),
+        cmiles: String::new(),
+        inchi_key: String::new(),
+        molecules: vec![mol.clone()],
+        initial_molecules: Vec::new(),
+        dataset: None,
+    };
+    let v = serde_json::to_value(&entry).unwrap();
+    assert!(v.get("initial_conformers").is_none());
+    assert!(serde_json::to_value(entry.full())
+        .unwrap()
+        .get("initial_molecules")
+        .is_none());
+
+    entry.initial_molecules.push(mol);
+    let v = serde_json::to_value(&entry).unwrap();
+    assert_eq!(
+        v["initial_conformers"],
+        serde_json::json!([entry.molecules[0].geometry])
+    );
+    let v = serde_json::to_value(entry.full()).unwrap();
+    assert_eq!(v["initial_molecules"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn missing_molecules() {
+    let ds =
+        TorsionDriveResultCollection::parse_file("testfiles/core-opt.json")
+            .unwrap();
+    let col: CollectionGetResponse = ds.into();
+    let results = col.into_results();
+    let s = read_to_string("testfiles/opt_procedure.json").unwrap();
+    let c: Response<OptimizationRecord> = serde_json::from_str(&s).unwrap();
+    let s = read_to_string("testfiles/molecules.json").unwrap();
+    let m: Response<Molecule> = serde_json::from_str(&s).unwrap();
+    let mol = m.data.into_iter().next().unwrap();
+
+    // the first three records are in the collection, and the fourth isn't
+    let mut records: Vec<_> = c.data.into_iter().take(4).collect();
+    for (rec, res) in records.iter_mut().zip(&results).take(3) {
+        rec.id = res.record_id().clone();
+    }
+    // only the first and last records have their molecule, the second's
+    // molecule wasn't returned, and the third's optimization wasn't either
+    let molecule_ids = HashMap::from([
+        (records[0].id.clone(), mol.id.clone()),
+        (records[1].id.clone(), "missing".to_owned()),
+        (records[3].id.clone(), mol.id.clone()),
+    ]);
+    let want = records[0].id.clone();
+    let molecules = HashMap::from([(mol.id.clone(), mol)]);
+
+    let got = make_opt_results(results, records, molecule_ids, molecules);
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[0].record.id, want);
+}
+
 #[tokio::test]
 async fn full() {
     let want = {
@@ -61,12 +227,14 @@ async fn full() {
     );
 
     let col = client.get_collection(col).await;
-    let mut got = client.torsion_drive_records(col, 400).await;
+    let mut got = client
+        .torsion_drive_records(col, 400, &SpecFilter::default())
+        .await;
 
-    got.sort_by_key(|g| g.0.id.clone());
+    got.sort_by_key(|g| g.record.id.clone());
     let got: Vec<_> = got
         .into_iter()
-        .map(|(a, b, c)| (a.id, b, c.len()))
+        .map(|r| (r.record.id, r.cmiles, r.molecules.len()))
         .collect();
 
     assert_eq!(got, want);
@@ -95,15 +263,17 @@ async fn full_opt() {
         TorsionDriveResultCollection::parse_file("testfiles/core-opt.json")
             .unwrap();
     let col: CollectionGetResponse = ds.into();
-    let mut got = client.optimization_records(col, 400).await;
+    let mut got = client
+        .optimization_records(col, 400, &SpecFilter::default())
+        .await;
 
-    got.sort_by_key(|g| g.0.id.clone());
+    got.sort_by_key(|g| g.record.id.clone());
     // NOTE: unlike above, comparing the length of the geometry (in atoms)
     // rather than the length of the conformers vector because it should always
     // contain a single conformer
     let got: Vec<_> = got
         .into_iter()
-        .map(|(a, b, c)| (a.id, b, c[0].len() / 3))
+        .map(|r| (r.record.id, r.cmiles, r.molecules[0].geometry.len() / 3))
         .collect();
 
     assert_eq!(got.len(), want.len());
@@ -0,0 +1,156 @@
+//! Units for the geometries and energies in the output. QCArchive stores
+//! everything in atomic units, bohr and hartree, so these are the defaults.
+
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{molecule::BOHR_TO_ANGSTROM, procedure::Record, ResultEntry};
+
+/// the conversion factors from hartree used by qcelemental (CODATA 2014)
+const HARTREE_TO_KCAL_PER_MOL: f64 = 627.509474;
+const HARTREE_TO_KJ_PER_MOL: f64 = 2625.499639;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum LengthUnit {
+    #[default]
+    #[serde(rename = "bohr")]
+    Bohr,
+    #[serde(rename = "angstrom")]
+    Angstrom,
+    #[serde(rename = "nm")]
+    Nanometer,
+}
+
+impl LengthUnit {
+    /// the factor to multiply a length in bohr by to convert it to `self`
+    pub fn bohr_factor(self) -> f64 {
+        match self {
+            LengthUnit::Bohr => 1.0,
+            LengthUnit::Angstrom => BOHR_TO_ANGSTROM,
+            LengthUnit::Nanometer => BOHR_TO_ANGSTROM / 10.0,
+        }
+    }
+}
+
+impl FromStr for LengthUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bohr" => Ok(Self::Bohr),
+            "angstrom" => Ok(Self::Angstrom),
+            "nm" => Ok(Self::Nanometer),
+            _ => Err(format!("unmatched LengthUnit: `{s}`")),
+        }
+    }
+}
+
+impl Display for LengthUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            LengthUnit::Bohr => "bohr",
+            LengthUnit::Angstrom => "angstrom",
+            LengthUnit::Nanometer => "nm",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum EnergyUnit {
+    #[default]
+    #[serde(rename = "hartree")]
+    Hartree,
+    #[serde(rename = "kcal/mol")]
+    KcalPerMol,
+    #[serde(rename = "kJ/mol")]
+    KjPerMol,
+}
+
+impl EnergyUnit {
+    /// the factor to multiply an energy in hartree by to convert it to `self`
+    pub fn hartree_factor(self) -> f64 {
+        match self {
+            EnergyUnit::Hartree => 1.0,
+            EnergyUnit::KcalPerMol => HARTREE_TO_KCAL_PER_MOL,
+            EnergyUnit::KjPerMol => HARTREE_TO_KJ_PER_MOL,
+        }
+    }
+}
+
+impl FromStr for EnergyUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hartree" => Ok(Self::Hartree),
+            "kcal/mol" => Ok(Self::KcalPerMol),
+            "kj/mol" => Ok(Self::KjPerMol),
+            _ => Err(format!("unmatched EnergyUnit: `{s}`")),
+        }
+    }
+}
+
+impl Display for EnergyUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            EnergyUnit::Hartree => "hartree",
+            EnergyUnit::KcalPerMol => "kcal/mol",
+            EnergyUnit::KjPerMol => "kJ/mol",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl<R: Record> ResultEntry<R> {
+    /// convert the geometries of the molecules in `self` from bohr to `length`
+    /// and the energies of its record from hartree to `energy`, along with
+    /// any gradients or Hessians. this should only be called once, after
+    /// anything relying on atomic units, like the connectivity filter, is done
+    /// with the entry
+    pub fn convert_units(&mut self, length: LengthUnit, energy: EnergyUnit) {
+        let factor = length.bohr_factor();
+        if factor != 1.0 {
+            for m in
+                self.molecules.iter_mut().chain(&mut self.initial_molecules)
+            {
+                m.geometry.iter_mut().for_each(|x| *x *= factor);
+            }
+            self.record.scale_lengths(factor);
+        }
+        let factor = energy.hartree_factor();
+        if factor != 1.0 {
+            self.record.scale_energies(factor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_units() {
+        for u in [
+            LengthUnit::Bohr,
+            LengthUnit::Angstrom,
+            LengthUnit::Nanometer,
+        ] {
+            assert_eq!(u.to_string().parse::<LengthUnit>(), Ok(u));
+            let json = serde_json::to_string(&u).unwrap();
+            assert_eq!(json, format!("\"{u}\""));
+        }
+        for u in [
+            EnergyUnit::Hartree,
+            EnergyUnit::KcalPerMol,
+            EnergyUnit::KjPerMol,
+        ] {
+            assert_eq!(u.to_string().parse::<EnergyUnit>(), Ok(u));
+            let json = serde_json::to_string(&u).unwrap();
+            assert_eq!(json, format!("\"{u}\""));
+        }
+        assert_eq!("KCAL/MOL".parse(), Ok(EnergyUnit::KcalPerMol));
+        assert!("ev".parse::<EnergyUnit>().is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Checking that the conformers of torsion drives actually sit at the grid
+//! angles they were constrained to.
+
+use std::{error::Error, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    molecule::{dihedral, Molecule},
+    procedure::{Record, TorsionDriveRecord},
+    Output,
+};
+
+/// A conformer whose dihedral angle differs from its grid angle
+#[derive(Debug, Serialize)]
+pub struct Deviation {
+    pub record_id: String,
+    pub grid_id: String,
+    pub dihedral: (usize, usize, usize, usize),
+
+    /// the grid angle in degrees
+    pub expected: f64,
+
+    /// the angle computed from the geometry in degrees
+    pub actual: f64,
+}
+
+/// return the magnitude of the difference between the angles `a` and `b` in
+/// degrees, accounting for periodicity, so the result is in [0, 180]
+fn angle_difference(a: f64, b: f64) -> f64 {
+    let d = (a - b).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+/// compute each driven dihedral of `record` in each of `conformers` and return
+/// those differing from their grid angle by more than `tolerance` degrees.
+/// `conformers` should be in the order of [Record::grid_points], as in
+/// [ResultEntry::conformers]. returns nothing for records that aren't torsion
+/// drives
+///
+/// [ResultEntry::conformers]: crate::ResultEntry::conformers
+pub fn check_dihedrals<R, G>(
+    record: &R,
+    conformers: impl IntoIterator<Item = G>,
+    tolerance: f64,
+) -> Vec<Deviation>
+where
+    R: Record,
+    G: AsRef<[f64]>,
+{
+    let (Some(grid_points), Some(dihedrals)) =
+        (record.grid_points(), record.dihedrals())
+    else {
+        return Vec::new();
+    };
+    let conformers: Vec<G> = conformers.into_iter().collect();
+    if conformers.len() != grid_points.len() {
+        warn!(
+            "record {} has {} conformers but {} grid points",
+            record.id(),
+            conformers.len(),
+            grid_points.len()
+        );
+    }
+    let mut ret = Vec::new();
+    for ((grid_id, angles), geom) in grid_points.iter().zip(&conformers) {
+        for (&dihedral_atoms, &expected) in dihedrals.iter().zip(angles) {
+            let actual = dihedral(geom.as_ref(), dihedral_atoms);
+            if angle_difference(actual, expected) > tolerance {
+                ret.push(Deviation {
+                    record_id: record.id().to_owned(),
+                    grid_id: grid_id.to_string(),
+                    dihedral: dihedral_atoms,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    ret
+}
+
+/// the parts of a torsion drive entry in the output of `get` or `convert`
+/// needed for validation. the geometries are either in `conformers` or in
+/// `molecules` if the output was written with `--full-molecules`
+#[derive(Deserialize)]
+pub struct OutputEntry {
+    pub record: TorsionDriveRecord,
+    #[serde(default)]
+    conformers: Vec<Vec<f64>>,
+    #[serde(default)]
+    molecules: Vec<Molecule>,
+}
+
+impl OutputEntry {
+    /// return the geometries of the conformers in the entry
+    pub fn conformers(&self) -> Vec<&[f64]> {
+        if self.molecules.is_empty() {
+            self.conformers.iter().map(Vec::as_slice).collect()
+        } else {
+            self.molecules
+                .iter()
+                .map(|m| m.geometry.as_slice())
+                .collect()
+        }
+    }
+}
+
+/// read the torsion drive entries from a file written by `get` or `convert`
+pub fn read_output(
+    path: impl AsRef<Path>,
+) -> Result<Vec<OutputEntry>, Box<dyn Error>> {
+    let s = std::fs::read_to_string(path)?;
+    let output: Output<OutputEntry> = serde_json::from_str(&s)?;
+    Ok(output.results)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use crate::{filter::DihedralFilter, procedure::Response, ResultEntry};
+
+    use super::*;
+
+    #[test]
+    fn angle_differences() {
+        assert_eq!(angle_difference(10.0, -10.0), 20.0);
+        assert_eq!(angle_difference(179.0, -179.0), 2.0);
+        assert_eq!(angle_difference(-180.0, 180.0), 0.0);
+    }
+
+    /// build a geometry for `record` where the atoms of its first dihedral
+    /// are arranged to give `angle` and every other atom is at the origin
+    fn geometry(record: &TorsionDriveRecord, angle: f64) -> Vec<f64> {
+        let (i, j, k, l) = record.keywords.dihedrals[0];
+        let natoms = i.max(j).max(k).max(l) + 1;
+        let mut ret = vec![0.0; 3 * natoms];
+        let t = angle.to_radians();
+        for (atom, pos) in [
+            (i, [0.0, 1.0, 0.0]),
+            (j, [0.0, 0.0, 0.0]),
+            (k, [1.5, 0.0, 0.0]),
+            (l, [1.5, t.cos(), t.sin()]),
+        ] {
+            ret[3 * atom..3 * atom + 3].copy_from_slice(&pos);
+        }
+        ret
+    }
+
+    #[test]
+    fn check_grid() {
+        let s = read_to_string("testfiles/procedure.json").unwrap();
+        let c: Response<TorsionDriveRecord> = serde_json::from_str(&s).unwrap();
+        let record = c
+            .data
+            .into_iter()
+            .find(|r| r.keywords.dihedrals.len() == 1)
+            .unwrap();
+        let grid_points = record.grid_points().unwrap();
+        let mut conformers: Vec<_> = grid_points
+            .iter()
+            .map(|(_, angles)| geometry(&record, angles[0] + 0.5))
+            .collect();
+        assert!(check_dihedrals(&record, &conformers, 1.0).is_empty());
+
+        // rotate the last conformer well away from its grid point
+        let (_, last) = grid_points.last().unwrap();
+        *conformers.last_mut().unwrap() = geometry(&record, last[0] + 30.0);
+        let got = check_dihedrals(&record, &conformers, 1.0);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].grid_id, grid_points.last().unwrap().0);
+        assert!(
+            (angle_difference(got[0].actual, got[0].expected) - 30.0).abs()
+                < 1e-8
+        );
+
+        // the filter should remove only that conformer and its grid point
+        let grid_ids: Vec<String> =
+            grid_points.iter().map(|(g, _)| g.to_string()).collect();
+        let s = read_to_string("testfiles/molecules.json").unwrap();
+        let c: Response<Molecule> = serde_json::from_str(&s).unwrap();
+        let mol = &c.data[0];
+        let molecules = conformers
+            .iter()
+            .map(|g| Molecule {
+                geometry: g.clone(),
+                ..mol.clone()
+            })
+            .collect();
+        let mut entries = vec![ResultEntry {
+            record,
+            cmiles: String::new(),
+            inchi_key: String::new(),
+            molecules,
+            initial_molecules: Vec::new(),
+            dataset: None,
+        }];
+        assert_eq!(DihedralFilter::new(1.0).apply(&mut entries), 1);
+        let entry = &entries[0];
+        let (bad, good) = grid_ids.split_last().unwrap();
+        assert_eq!(entry.molecules.len(), good.len());
+        assert!(entry.conformers().eq(&conformers[..good.len()]));
+        let record = &entry.record;
+        for g in good {
+            assert!(record.final_energies.contains_key(g));
+            assert!(record.minimum_positions.contains_key(g));
+        }
+        assert!(!record.final_energies.contains_key(bad));
+        assert!(!record.minimum_positions.contains_key(bad));
+        assert!(!record.optimization_history.contains_key(bad));
+        assert!(check_dihedrals(record, entry.conformers(), 1.0).is_empty());
+    }
+}